@@ -0,0 +1,295 @@
+//! Timings of the coordinate maths, meshing and path finding of every
+//! primitive, on maps from 100 by 100 to 1000 by 1000 cells, and of path
+//! finding over clusters of cells, and of keeping those clusters up with a
+//! changed cell.
+//!
+//! Run with `cargo bench`, or `cargo bench -- <filter>` for one group.
+
+use bevy::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use human_action::grids::{
+    coordinates::{
+        Coords, HexCoord, HexOrientation, SquareCoord, TriangleCoord, TriangleNeighbours,
+    },
+    hierarchy::PathHierarchy,
+    pathfinding::find_path,
+    primitives::{GridAlign, GridPrimitive, GridWrap, Hexes, Squares, Triangles},
+    GridLayout,
+};
+
+const SIZES: [u32; 3] = [100, 300, 1000];
+
+fn triangles() -> Triangles {
+    Triangles {
+        size: 1.0,
+        alignment: GridAlign::XZ,
+        neighbors: TriangleNeighbours::Strict,
+        layer: 0.0,
+        wrap: GridWrap::default(),
+    }
+}
+
+fn squares() -> Squares {
+    Squares {
+        size: 1.0,
+        alignment: GridAlign::XZ,
+        layer: 0.0,
+        wrap: GridWrap::default(),
+    }
+}
+
+fn hexes() -> Hexes {
+    Hexes {
+        size: 1.0,
+        alignment: GridAlign::XZ,
+        orientation: HexOrientation::PointyUp,
+        layer: 0.0,
+        wrap: GridWrap::default(),
+    }
+}
+
+/// World positions of the cell centres of a `size` by `size` map
+fn positions<T: GridLayout>(grid: &T, size: u32) -> Vec<Vec3> {
+    grid.cells(size, size)
+        .iter()
+        .map(|cell| grid.cell_pos(cell))
+        .collect()
+}
+
+fn coordinates(c: &mut Criterion) {
+    let (triangles, squares, hexes) = (triangles(), squares(), hexes());
+    for size in SIZES {
+        let mut group = c.benchmark_group(format!("coordinates/{size}x{size}"));
+        group.sample_size(10);
+
+        let cells = triangles.cells(size, size);
+        let world = positions(&triangles, size);
+        group.throughput(Throughput::Elements(cells.len() as u64));
+        group.bench_function("triangles/new_from_world_pos", |b| {
+            b.iter(|| {
+                for pos in &world {
+                    black_box(TriangleCoord::new_from_world_pos(*pos, &triangles));
+                }
+            })
+        });
+        group.bench_function("triangles/to_vec3", |b| {
+            b.iter(|| {
+                for cell in &cells {
+                    black_box(cell.to_vec3(&triangles));
+                }
+            })
+        });
+        group.bench_function("triangles/neighbours", |b| {
+            b.iter(|| {
+                for cell in &cells {
+                    black_box(cell.neighbours());
+                }
+            })
+        });
+        group.bench_function("triangles/distance", |b| {
+            b.iter(|| {
+                for cell in &cells {
+                    black_box(cell.distance(&TriangleCoord::ZERO));
+                }
+            })
+        });
+
+        let cells = squares.cells(size, size);
+        let world = positions(&squares, size);
+        group.throughput(Throughput::Elements(cells.len() as u64));
+        group.bench_function("squares/new_from_world_pos", |b| {
+            b.iter(|| {
+                for pos in &world {
+                    black_box(SquareCoord::new_from_world_pos(*pos, &squares));
+                }
+            })
+        });
+        group.bench_function("squares/to_vec3", |b| {
+            b.iter(|| {
+                for cell in &cells {
+                    black_box(cell.to_vec3(&squares));
+                }
+            })
+        });
+        group.bench_function("squares/neighbours", |b| {
+            b.iter(|| {
+                for cell in &cells {
+                    black_box(cell.neighbours());
+                }
+            })
+        });
+        group.bench_function("squares/distance", |b| {
+            b.iter(|| {
+                for cell in &cells {
+                    black_box(cell.distance(&SquareCoord::ZERO));
+                }
+            })
+        });
+
+        let cells = hexes.cells(size, size);
+        let world = positions(&hexes, size);
+        group.throughput(Throughput::Elements(cells.len() as u64));
+        group.bench_function("hexes/new_from_world_pos", |b| {
+            b.iter(|| {
+                for pos in &world {
+                    black_box(HexCoord::new_from_world_pos(*pos, &hexes));
+                }
+            })
+        });
+        group.bench_function("hexes/to_vec3", |b| {
+            b.iter(|| {
+                for cell in &cells {
+                    black_box(cell.to_vec3(&hexes));
+                }
+            })
+        });
+        group.bench_function("hexes/neighbours", |b| {
+            b.iter(|| {
+                for cell in &cells {
+                    black_box(cell.neighbours());
+                }
+            })
+        });
+        group.bench_function("hexes/distance", |b| {
+            b.iter(|| {
+                for cell in &cells {
+                    black_box(cell.distance(&HexCoord::ZERO));
+                }
+            })
+        });
+        group.finish();
+    }
+}
+
+/// Cell mesh and the transforms of every cell entity, as `spawn_grid` and
+/// `mesh_cells` build them
+fn meshing(c: &mut Criterion) {
+    fn bench<T: GridLayout>(c: &mut Criterion, name: &str, grid: &T) {
+        let mut group = c.benchmark_group(format!("meshing/{name}"));
+        group.sample_size(10);
+        for size in SIZES {
+            group.throughput(Throughput::Elements(size as u64 * size as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+                b.iter(|| {
+                    let (mesh, positions) = GridPrimitive::to_grid(grid, size, size);
+                    let transforms: Vec<Transform> = grid
+                        .cells(size, size)
+                        .iter()
+                        .map(|cell| grid.cell_transform(cell))
+                        .collect();
+                    black_box((mesh, positions, transforms))
+                })
+            });
+        }
+        group.finish();
+    }
+    bench(c, "triangles", &triangles());
+    bench(c, "squares", &squares());
+    bench(c, "hexes", &hexes());
+}
+
+/// Corner to corner across a map split by a wall with a single gap at the
+/// far end, so the search has to go around it
+fn pathfinding(c: &mut Criterion) {
+    fn bench<T: GridLayout>(c: &mut Criterion, name: &str, grid: &T) {
+        let mut group = c.benchmark_group(format!("pathfinding/{name}"));
+        group.sample_size(10);
+        for size in SIZES {
+            let cells = grid.cells(size, size);
+            let (start, goal) = (cells[0], cells[cells.len() - 1]);
+            let side = size as i32;
+            let cost = |cell: &T::Coord| {
+                let (col, row) = grid.offset(cell);
+                let inside = (0..side).contains(&col) && (0..side).contains(&row);
+                let wall = col == side / 2 && row < side - 2;
+                (inside && !wall).then_some(1)
+            };
+            assert!(find_path(grid, start, goal, cost).is_some());
+            group.throughput(Throughput::Elements(size as u64 * size as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+                b.iter(|| black_box(find_path(grid, start, goal, cost)))
+            });
+        }
+        group.finish();
+    }
+    bench(c, "triangles", &triangles());
+    bench(c, "squares", &squares());
+    bench(c, "hexes", &hexes());
+}
+
+fn hierarchy(c: &mut Criterion) {
+    fn bench<T: GridLayout>(c: &mut Criterion, name: &str, grid: &T) {
+        let mut group = c.benchmark_group(format!("hierarchy/{name}"));
+        group.sample_size(10);
+        for size in SIZES {
+            let cells = grid.cells(size, size);
+            let (start, goal) = (cells[0], cells[cells.len() - 1]);
+            let side = size as i32;
+            let cost = |cell: &T::Coord| {
+                let (col, row) = grid.offset(cell);
+                let wall = col == side / 2 && row < side - 2;
+                (!wall).then_some(1)
+            };
+            let mut hierarchy = PathHierarchy::new(10);
+            hierarchy.update(grid, cells, cost);
+            assert!(hierarchy.find_path(grid, start, goal).is_some());
+            group.throughput(Throughput::Elements(size as u64 * size as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+                b.iter(|| black_box(hierarchy.find_path(grid, start, goal)))
+            });
+        }
+        group.finish();
+    }
+    bench(c, "squares", &squares());
+    bench(c, "hexes", &hexes());
+}
+
+fn hierarchy_update(c: &mut Criterion) {
+    fn bench<T: GridLayout>(c: &mut Criterion, name: &str, grid: &T) {
+        let mut group = c.benchmark_group(format!("hierarchy_update/{name}"));
+        group.sample_size(10);
+        for size in SIZES {
+            let cells = grid.cells(size, size);
+            let side = size as i32;
+            // one of the two cells of the gap in the wall, opened and shut
+            let door = *cells
+                .iter()
+                .find(|cell| grid.offset(cell) == (side / 2, side - 1))
+                .unwrap();
+            let cost = |cell: &T::Coord, shut: bool| {
+                let (col, row) = grid.offset(cell);
+                let wall = col == side / 2 && (row < side - 2 || (shut && *cell == door));
+                (!wall).then_some(1)
+            };
+            let mut hierarchy = PathHierarchy::new(10);
+            hierarchy.update(grid, cells.iter().copied(), |c| cost(c, false));
+            let mut shut = false;
+            group.throughput(Throughput::Elements(size as u64 * size as u64));
+            group.bench_with_input(BenchmarkId::new("cells", size), &size, |b, _| {
+                b.iter(|| {
+                    shut = !shut;
+                    black_box(hierarchy.update_cells(grid, [door], |c| cost(c, shut)))
+                })
+            });
+            group.bench_with_input(BenchmarkId::new("all", size), &size, |b, _| {
+                b.iter(|| {
+                    shut = !shut;
+                    black_box(hierarchy.update(grid, [], |c| cost(c, shut)))
+                })
+            });
+        }
+        group.finish();
+    }
+    bench(c, "squares", &squares());
+    bench(c, "hexes", &hexes());
+}
+
+criterion_group!(
+    benches,
+    coordinates,
+    meshing,
+    pathfinding,
+    hierarchy,
+    hierarchy_update
+);
+criterion_main!(benches);
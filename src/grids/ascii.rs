@@ -0,0 +1,274 @@
+use std::{error::Error, fmt};
+
+use super::{
+    coordinates::{HexCoord, HexOrientation, SquareCoord, TriangleCoord},
+    layers::GridLayer,
+    primitives::{Hexes, Squares, Triangles},
+    topology::Topology,
+    GridLayout,
+};
+
+/// Text drawing of a `width` by `height` map, one character per cell:
+/// - squares in rows separated by spaces, North up
+/// - pointy hexes in rows going south, odd rows indented by one character
+/// - flat hexes in columns two lines apart, odd columns a line lower
+/// - triangles in rows of touching characters, North up
+pub trait TextLayout: GridLayout {
+    /// Character column and line the cell is drawn at
+    fn text_pos(&self, coord: &Self::Coord, height: u32) -> (usize, usize);
+
+    /// Cell drawn at a character column and line, if any
+    fn at_text_pos(&self, x: usize, y: usize, height: u32) -> Option<Self::Coord>;
+
+    /// Height of the map drawn over so many lines
+    fn text_height(&self, lines: usize) -> u32 {
+        lines as u32
+    }
+}
+
+impl TextLayout for Squares {
+    fn text_pos(&self, coord: &SquareCoord, height: u32) -> (usize, usize) {
+        (2 * coord.q as usize, (height as i32 - 1 - coord.r) as usize)
+    }
+
+    fn at_text_pos(&self, x: usize, y: usize, height: u32) -> Option<SquareCoord> {
+        (x % 2 == 0).then(|| SquareCoord {
+            q: x as i32 / 2,
+            r: height as i32 - 1 - y as i32,
+        })
+    }
+}
+
+impl TextLayout for Hexes {
+    fn text_pos(&self, coord: &HexCoord, _height: u32) -> (usize, usize) {
+        let (col, row) = self.offset(coord);
+        match self.orientation {
+            HexOrientation::PointyUp => ((2 * col + row % 2) as usize, row as usize),
+            HexOrientation::FlatUp => (2 * col as usize, (2 * row + col % 2) as usize),
+        }
+    }
+
+    fn at_text_pos(&self, x: usize, y: usize, _height: u32) -> Option<HexCoord> {
+        let (x, y) = (x as i32, y as i32);
+        let (col, row) = match self.orientation {
+            HexOrientation::PointyUp => ((x - y % 2) / 2, y),
+            HexOrientation::FlatUp => (x / 2, (y - (x / 2) % 2) / 2),
+        };
+        let cell = self.at_offset(col, row);
+        (self.text_pos(&cell, 0) == (x as usize, y as usize)).then_some(cell)
+    }
+
+    fn text_height(&self, lines: usize) -> u32 {
+        match self.orientation {
+            HexOrientation::PointyUp => lines as u32,
+            HexOrientation::FlatUp => (lines as u32).div_ceil(2),
+        }
+    }
+}
+
+impl TextLayout for Triangles {
+    fn text_pos(&self, coord: &TriangleCoord, height: u32) -> (usize, usize) {
+        (
+            coord.column() as usize,
+            (height as i32 - 1 - coord.r) as usize,
+        )
+    }
+
+    fn at_text_pos(&self, x: usize, y: usize, height: u32) -> Option<TriangleCoord> {
+        Some(TriangleCoord::from_column(
+            x as i32,
+            height as i32 - 1 - y as i32,
+        ))
+    }
+}
+
+/// Character of a drawing that does not stand for a cell, counting from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsciiError {
+    pub line: usize,
+    pub column: usize,
+    pub glyph: char,
+}
+
+impl fmt::Display for AsciiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unexpected {:?} at line {}, column {}",
+            self.glyph, self.line, self.column
+        )
+    }
+}
+
+impl Error for AsciiError {}
+
+/// Draws the cells of a `width` by `height` map with the character `glyph` gives each
+pub fn to_ascii<T: TextLayout>(
+    grid: &T,
+    width: u32,
+    height: u32,
+    glyph: impl Fn(&T::Coord) -> char,
+) -> String {
+    let mut lines: Vec<Vec<char>> = Vec::new();
+    for cell in grid.cells(width, height) {
+        let (x, y) = grid.text_pos(&cell, height);
+        if lines.len() <= y {
+            lines.resize(y + 1, Vec::new());
+        }
+        let line = &mut lines[y];
+        if line.len() <= x {
+            line.resize(x + 1, ' ');
+        }
+        line[x] = glyph(&cell);
+    }
+    lines
+        .iter()
+        .map(|line| line.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reads a drawing made by `to_ascii`, turning the character of each cell into
+/// a value. Blank lines around the drawing and indentation common to all of its
+/// lines are ignored, so drawings can be written inline in tests.
+pub fn from_ascii<T: TextLayout, V>(
+    grid: &T,
+    text: &str,
+    value: impl Fn(char) -> Option<V>,
+) -> Result<GridLayer<T::Coord, V>, AsciiError> {
+    let lines: Vec<&str> = text.lines().skip_while(|l| l.trim().is_empty()).collect();
+    let end = lines
+        .iter()
+        .rposition(|l| !l.trim().is_empty())
+        .map_or(0, |i| i + 1);
+    let lines = &lines[..end];
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.chars().take_while(|c| c.is_whitespace()).count())
+        .min()
+        .unwrap_or(0);
+    let height = grid.text_height(lines.len());
+    let mut layer = GridLayer::new();
+    for (y, line) in lines.iter().enumerate() {
+        for (x, glyph) in line.chars().skip(indent).enumerate() {
+            if glyph == ' ' {
+                continue;
+            }
+            let error = AsciiError {
+                line: y + 1,
+                column: indent + x + 1,
+                glyph,
+            };
+            let cell = grid.at_text_pos(x, y, height).ok_or(error.clone())?;
+            layer.insert(cell, value(glyph).ok_or(error)?);
+        }
+    }
+    Ok(layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::TriangleNeighbours,
+        primitives::{GridAlign, GridWrap},
+        regions::flood_fill,
+    };
+
+    fn walls(glyph: char) -> Option<bool> {
+        match glyph {
+            '#' => Some(true),
+            '.' => Some(false),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn drawings_round_trip() {
+        let hexes = |orientation| Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let pointy = "
+            . . # .
+             . # . .
+            . # . .";
+        let flat = "
+            .   #
+              .   .
+            #   .
+              #   .";
+        for (grid, text, width, height) in [
+            (hexes(HexOrientation::PointyUp), pointy, 4, 3),
+            (hexes(HexOrientation::FlatUp), flat, 4, 2),
+        ] {
+            let layer = from_ascii(&grid, text, walls).unwrap();
+            assert_eq!(layer.len(), (width * height) as usize);
+            let drawn = to_ascii(&grid, width, height, |c| {
+                if *layer.get(c).unwrap() {
+                    '#'
+                } else {
+                    '.'
+                }
+            });
+            let expected: Vec<_> = text.lines().skip(1).map(|l| &l[12..]).collect();
+            assert_eq!(drawn, expected.join("\n"));
+        }
+
+        // wide spaces indent by one character each, whatever their bytes
+        let wide = "\u{3000}\u{3000}. #\n\u{3000}\u{3000} . x";
+        let error = from_ascii(&hexes(HexOrientation::PointyUp), wide, walls)
+            .err()
+            .unwrap();
+        assert_eq!((error.line, error.column, error.glyph), (2, 6, 'x'));
+    }
+
+    #[test]
+    fn flood_fill_snapshot() {
+        let grid = Squares {
+            size: 1.0,
+            alignment: GridAlign::XY,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let maze = from_ascii(
+            &grid,
+            "
+            . . # .
+            . # . .
+            . # . .",
+            walls,
+        )
+        .unwrap();
+        let filled = flood_fill(SquareCoord { q: 0, r: 0 }, |c| maze.get(c) == Some(&false));
+        let drawn = to_ascii(&grid, 4, 3, |c| match maze.get(c) {
+            Some(true) => '#',
+            _ if filled.contains(c) => '~',
+            _ => '.',
+        });
+        assert_eq!(drawn, "~ ~ # .\n~ # . .\n~ # . .");
+
+        let triangles = Triangles {
+            size: 1.0,
+            alignment: GridAlign::XY,
+            neighbors: TriangleNeighbours::Strict,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let layer = from_ascii(&triangles, "..#.\n#...", walls).unwrap();
+        assert_eq!(layer.get(&TriangleCoord::from_column(2, 1)), Some(&true));
+        assert_eq!(layer.get(&TriangleCoord::from_column(0, 0)), Some(&true));
+        assert_eq!(
+            from_ascii(&triangles, "..x.", walls).err().unwrap(),
+            AsciiError {
+                line: 1,
+                column: 3,
+                glyph: 'x'
+            }
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashMap},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use super::{
+    add_grid_systems,
+    primitives::{Hexes, Squares, Triangles, WrapError},
+    spawn_grid,
+    topology::Topology,
+    GridConfig, GridLayout, GridMap,
+};
+
+/// Grid described by a `.grid.ron` file
+#[derive(Clone, Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "5b0e7c1e-93a4-4c57-9d1b-3f6a8e2d4c71"]
+pub struct GridAsset {
+    pub primitive: GridShape,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Primitive of a grid asset, along with its parameters
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum GridShape {
+    Triangles(Triangles),
+    Squares(Squares),
+    Hexes(Hexes),
+}
+
+impl GridShape {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GridShape::Triangles(_) => Triangles::NAME,
+            GridShape::Squares(_) => Squares::NAME,
+            GridShape::Hexes(_) => Hexes::NAME,
+        }
+    }
+
+    /// Checks the primitive wraps by sizes it can wrap by
+    pub fn check_wrap(&self) -> Result<(), WrapError> {
+        match self {
+            GridShape::Triangles(triangles) => triangles.check_wrap(),
+            GridShape::Squares(squares) => squares.check_wrap(),
+            GridShape::Hexes(hexes) => hexes.check_wrap(),
+        }
+    }
+}
+
+/// Primitives a `GridShape` may describe
+pub trait ShapedGrid: Sized {
+    const NAME: &'static str;
+
+    fn from_shape(shape: &GridShape) -> Option<Self>;
+    fn shape(&self) -> GridShape;
+}
+
+impl ShapedGrid for Triangles {
+    const NAME: &'static str = "Triangles";
+
+    fn from_shape(shape: &GridShape) -> Option<Self> {
+        match shape {
+            GridShape::Triangles(triangles) => Some(*triangles),
+            _ => None,
+        }
+    }
+
+    fn shape(&self) -> GridShape {
+        GridShape::Triangles(*self)
+    }
+}
+
+impl ShapedGrid for Squares {
+    const NAME: &'static str = "Squares";
+
+    fn from_shape(shape: &GridShape) -> Option<Self> {
+        match shape {
+            GridShape::Squares(squares) => Some(*squares),
+            _ => None,
+        }
+    }
+
+    fn shape(&self) -> GridShape {
+        GridShape::Squares(*self)
+    }
+}
+
+impl ShapedGrid for Hexes {
+    const NAME: &'static str = "Hexes";
+
+    fn from_shape(shape: &GridShape) -> Option<Self> {
+        match shape {
+            GridShape::Hexes(hexes) => Some(hexes.clone()),
+            _ => None,
+        }
+    }
+
+    fn shape(&self) -> GridShape {
+        GridShape::Hexes(self.clone())
+    }
+}
+
+/// Primitive each grid asset must describe, by path, for the plugin loading
+/// it cannot swap the type of its `GridConfig`
+#[derive(Resource, Clone, Default)]
+pub struct GridShapes(Arc<RwLock<HashMap<PathBuf, &'static str>>>);
+
+impl GridShapes {
+    pub fn expect(&self, path: impl Into<PathBuf>, shape: &'static str) {
+        self.0.write().unwrap().insert(path.into(), shape);
+    }
+
+    /// Fails when the asset at `path` describes another primitive than the
+    /// one expected of it
+    pub fn check(&self, path: &Path, shape: &GridShape) -> Result<(), bevy::asset::Error> {
+        match self.0.read().unwrap().get(path) {
+            Some(expected) if *expected != shape.name() => Err(bevy::asset::Error::msg(format!(
+                "grid asset {} describes {}, expected {}",
+                path.display(),
+                shape.name(),
+                expected
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct GridAssetLoader {
+    shapes: GridShapes,
+}
+
+impl FromWorld for GridAssetLoader {
+    fn from_world(world: &mut World) -> Self {
+        GridAssetLoader {
+            shapes: world
+                .get_resource_or_insert_with(GridShapes::default)
+                .clone(),
+        }
+    }
+}
+
+impl AssetLoader for GridAssetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let asset: GridAsset = ron::de::from_bytes(bytes)?;
+            self.shapes.check(load_context.path(), &asset.primitive)?;
+            asset.primitive.check_wrap().map_err(|e| {
+                bevy::asset::Error::msg(format!("grid asset {} {e}", load_context.path().display()))
+            })?;
+            load_context.set_default_asset(LoadedAsset::new(asset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["grid.ron"]
+    }
+}
+
+/// Asset the grid of primitive `T` is built from
+#[derive(Resource)]
+pub struct GridSource<T> {
+    pub handle: Handle<GridAsset>,
+    marker: PhantomData<fn() -> T>,
+}
+
+/// Builds the grid from a `.grid.ron` asset instead of a hard-coded config,
+/// and rebuilds its cells whenever the asset changes. Changes are only
+/// picked up from disk when the `AssetPlugin` watches for them. The asset
+/// must keep describing a grid of `T` wrapping by sizes it can wrap by: one
+/// switched to another primitive or to a bad wrap fails to load, leaving the
+/// grid as it was.
+pub struct GridAssetPlugin<T> {
+    path: String,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> GridAssetPlugin<T> {
+    /// `path` is relative to the assets folder, e.g. "grids/main.grid.ron"
+    pub fn new(path: impl Into<String>) -> Self {
+        GridAssetPlugin {
+            path: path.into(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: GridLayout + ShapedGrid> Plugin for GridAssetPlugin<T> {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<Assets<GridAsset>>() {
+            app.add_asset::<GridAsset>()
+                .init_asset_loader::<GridAssetLoader>();
+        }
+        app.world
+            .get_resource_or_insert_with(GridShapes::default)
+            .expect(&self.path, T::NAME);
+        let path = self.path.clone();
+        app.add_startup_system(move |mut commands: Commands, server: Res<AssetServer>| {
+            commands.insert_resource(GridSource::<T> {
+                handle: server.load(path.as_str()),
+                marker: PhantomData,
+            });
+        })
+        .add_system(rebuild_grid::<T>);
+        add_grid_systems::<T>(app);
+    }
+}
+
+/// Replaces the `GridConfig` and respawns every cell when the grid asset is
+/// loaded or modified
+pub fn rebuild_grid<T: GridLayout + ShapedGrid>(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<GridAsset>>,
+    source: Option<Res<GridSource<T>>>,
+    assets: Res<Assets<GridAsset>>,
+    mut map: ResMut<GridMap<T::Coord>>,
+) {
+    let Some(source) = source else {
+        return;
+    };
+    for event in events.iter() {
+        let (AssetEvent::Created { handle } | AssetEvent::Modified { handle }) = event else {
+            continue;
+        };
+        if *handle != source.handle {
+            continue;
+        }
+        let Some(asset) = assets.get(handle) else {
+            continue;
+        };
+        let Some(primitive) = T::from_shape(&asset.primitive) else {
+            error!(
+                "grid asset describes {}, expected {}",
+                asset.primitive.name(),
+                T::NAME
+            );
+            continue;
+        };
+        let config = GridConfig(primitive);
+        spawn_grid(
+            &mut commands,
+            &config,
+            (asset.width, asset.height),
+            &mut map,
+        );
+        commands.insert_resource(config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_files_describe_primitives() {
+        let asset: GridAsset = ron::from_str(
+            "(
+                primitive: Hexes((
+                    size: 1.0,
+                    alignment: XZ,
+                    orientation: FlatUp,
+                    layer: 0.0,
+                )),
+                width: 4,
+                height: 3,
+            )",
+        )
+        .unwrap();
+        let hexes = Hexes::from_shape(&asset.primitive).unwrap();
+        assert!(Squares::from_shape(&asset.primitive).is_none());
+        assert!(hexes.wrap.width.is_none());
+        assert_eq!(GridConfig(hexes).to_grid(4, 3).1.len(), 12);
+
+        let shapes = GridShapes::default();
+        let path = Path::new("grids/main.grid.ron");
+        assert!(shapes.check(path, &asset.primitive).is_ok());
+        shapes.expect(path, Squares::NAME);
+        let error = shapes.check(path, &asset.primitive).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("describes Hexes, expected Squares"));
+        shapes.expect(path, Hexes::NAME);
+        assert!(shapes.check(path, &asset.primitive).is_ok());
+    }
+}
@@ -0,0 +1,266 @@
+use bevy::{
+    prelude::*,
+    tasks::{ComputeTaskPool, TaskPool},
+};
+use std::hash::Hash;
+
+use super::{
+    coordinates::{
+        Coords, HexCoord, SquareCoord, SquareNeighbours, TriangleCoord, TriangleNeighbours,
+    },
+    layers::GridLayer,
+    topology::Topology,
+    GridConfig, GridLayout,
+};
+
+/// Coordinates with more than one way of counting the cells around them
+pub trait Neighbourhood: Coords + Copy + Eq + Hash + Send + Sync + 'static {
+    type Mode: Copy + Default + Send + Sync;
+
+    fn around(&self, mode: &Self::Mode) -> Vec<Self>;
+}
+
+impl Neighbourhood for TriangleCoord {
+    type Mode = TriangleNeighbours;
+
+    fn around(&self, mode: &TriangleNeighbours) -> Vec<TriangleCoord> {
+        self.neighbours_by(mode)
+    }
+}
+
+impl Neighbourhood for SquareCoord {
+    type Mode = SquareNeighbours;
+
+    fn around(&self, mode: &SquareNeighbours) -> Vec<SquareCoord> {
+        self.neighbours_by(mode)
+    }
+}
+
+/// Hexes only ever have the six across their edges
+impl Neighbourhood for HexCoord {
+    type Mode = ();
+
+    fn around(&self, _: &()) -> Vec<HexCoord> {
+        self.neighbours()
+    }
+}
+
+/// New value of a cell out of its value and those of its neighbours
+pub type Rule<V> = Box<dyn Fn(&V, &[&V]) -> V + Send + Sync>;
+
+/// Steps every cell of a layer at once by a rule, e.g. caves smoothed out of
+/// noise, fire spreading through forest or the Game of Life. New values are
+/// written to a second layer swapped in once all cells are done, so every
+/// cell reads the values of the step before.
+#[derive(Resource)]
+pub struct CellularAutomaton<K: Neighbourhood, V> {
+    pub neighbourhood: K::Mode,
+    /// Value of the neighbours off the layer, left out of the rule when `None`
+    pub outside: Option<V>,
+    /// Steps the cells on the threads of the `ComputeTaskPool`
+    pub parallel: bool,
+    pub paused: bool,
+    rule: Rule<V>,
+    back: GridLayer<K, V>,
+}
+
+impl<K: Neighbourhood, V: Send + Sync + 'static> CellularAutomaton<K, V> {
+    pub fn new(rule: impl Fn(&V, &[&V]) -> V + Send + Sync + 'static) -> Self {
+        CellularAutomaton {
+            neighbourhood: default(),
+            outside: None,
+            parallel: false,
+            paused: false,
+            rule: Box::new(rule),
+            back: GridLayer::new(),
+        }
+    }
+
+    /// New value of one cell, wrapping around the seams of the `grid`
+    fn next<T: Topology<K>>(&self, grid: &T, layer: &GridLayer<K, V>, cell: &K, value: &V) -> V {
+        let neighbours: Vec<&V> = cell
+            .around(&self.neighbourhood)
+            .iter()
+            .filter_map(|n| layer.get(&grid.normalize(n)).or(self.outside.as_ref()))
+            .collect();
+        (self.rule)(value, &neighbours)
+    }
+
+    /// Applies the rule once to every cell of the layer
+    pub fn step<T: Topology<K> + Sync>(&mut self, grid: &T, layer: &mut GridLayer<K, V>) {
+        let mut back = std::mem::take(&mut self.back);
+        back.clear();
+        if self.parallel {
+            let cells: Vec<(&K, &V)> = layer.iter().collect();
+            let pool = ComputeTaskPool::init(TaskPool::default);
+            let size = (cells.len() / pool.thread_num().max(1)).max(64);
+            let this = &*self;
+            let read = &*layer;
+            let chunks = pool.scope(|s| {
+                for chunk in cells.chunks(size) {
+                    s.spawn(async move {
+                        chunk
+                            .iter()
+                            .map(|(cell, value)| (**cell, this.next(grid, read, cell, value)))
+                            .collect::<Vec<_>>()
+                    });
+                }
+            });
+            for (cell, value) in chunks.into_iter().flatten() {
+                back.insert(cell, value);
+            }
+        } else {
+            for (cell, value) in layer.iter() {
+                back.insert(*cell, self.next(grid, layer, cell, value));
+            }
+        }
+        std::mem::swap(layer, &mut back);
+        self.back = back;
+    }
+}
+
+/// Steps the `GridLayer` of `V` on the grid of `T` once a frame by its
+/// `CellularAutomaton`, unless paused
+pub fn step_automaton<T, V>(
+    grid: Res<GridConfig<T>>,
+    mut automaton: ResMut<CellularAutomaton<T::Coord, V>>,
+    mut layer: ResMut<GridLayer<T::Coord, V>>,
+) where
+    T: GridLayout + Topology<T::Coord> + Sync,
+    T::Coord: Neighbourhood,
+    V: Send + Sync + 'static,
+{
+    if automaton.paused {
+        return;
+    }
+    automaton.step(&grid.0, &mut layer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        generation::mix,
+        primitives::{GridAlign, GridWrap, Squares, Triangles},
+        GridPlugin,
+    };
+
+    fn squares(wrap: GridWrap) -> Squares {
+        Squares {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            layer: 0.0,
+            wrap,
+        }
+    }
+
+    /// Conway's Game of Life
+    fn life(alive: &bool, around: &[&bool]) -> bool {
+        let n = around.iter().filter(|a| ***a).count();
+        n == 3 || (*alive && n == 2)
+    }
+
+    fn alive(layer: &GridLayer<SquareCoord, bool>) -> Vec<(i32, i32)> {
+        let mut cells: Vec<_> = layer
+            .iter()
+            .filter(|(_, a)| **a)
+            .map(|(c, _)| (c.q, c.r))
+            .collect();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn gliders_wrap_around_the_map() {
+        let grid = squares(GridWrap {
+            width: Some(6),
+            height: Some(6),
+        });
+        let glider = [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)];
+        let mut layer: GridLayer<SquareCoord, bool> = grid
+            .cells(6, 6)
+            .into_iter()
+            .map(|c| (c, glider.contains(&(c.q, c.r))))
+            .collect();
+        let start = alive(&layer);
+
+        let mut serial = CellularAutomaton::new(life);
+        let mut parallel = CellularAutomaton::new(life);
+        parallel.parallel = true;
+        let mut other = layer.clone();
+        // a glider moves one cell diagonally every four steps, back home after 24
+        for _ in 0..24 {
+            serial.step(&grid, &mut layer);
+            parallel.step(&grid, &mut other);
+            assert_eq!(alive(&layer), alive(&other));
+        }
+        assert_eq!(alive(&layer), start);
+
+        // with four neighbours the glider falls apart
+        let mut von_neumann = CellularAutomaton::new(life);
+        von_neumann.neighbourhood = SquareNeighbours::VonNeumann;
+        von_neumann.step(&grid, &mut layer);
+        assert_eq!(alive(&layer), [(1, 1), (1, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn caves_grow_from_noise() {
+        let grid = squares(GridWrap::default());
+        let mut app = App::new();
+        app.add_plugin(GridPlugin::new(grid));
+        let walls: GridLayer<SquareCoord, bool> = grid
+            .cells(20, 20)
+            .into_iter()
+            .map(|c| (c, mix((c.q * 20 + c.r) as u64) % 100 < 45))
+            .collect();
+        let caves = || {
+            let mut caves = CellularAutomaton::new(|wall: &bool, around: &[&bool]| {
+                around.iter().filter(|w| ***w).count() >= if *wall { 4 } else { 5 }
+            });
+            caves.outside = Some(true);
+            caves
+        };
+        let mut expected = walls.clone();
+        let mut by_hand = caves();
+        for _ in 0..3 {
+            by_hand.step(&grid, &mut expected);
+        }
+        app.insert_resource(walls)
+            .insert_resource(caves())
+            .add_system(
+                step_automaton::<Squares, bool>.run_if(resource_exists::<GridConfig<Squares>>()),
+            );
+        for _ in 0..3 {
+            app.update();
+        }
+        app.world
+            .resource_mut::<CellularAutomaton<SquareCoord, bool>>()
+            .paused = true;
+        app.update();
+        let walls = app.world.resource::<GridLayer<SquareCoord, bool>>();
+        assert_eq!(walls.len(), 400);
+        assert!(walls.iter().all(|(c, w)| expected.get(c) == Some(w)));
+        // the corners walled in by the cells off the map
+        assert!(walls.get(&SquareCoord::ZERO).unwrap());
+
+        // a triangle counts 3 neighbours strictly, 12 expanded
+        let triangles = Triangles {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            neighbors: TriangleNeighbours::Strict,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let count = |mode| {
+            let mut layer: GridLayer<TriangleCoord, usize> =
+                triangles.cells(6, 6).into_iter().map(|c| (c, 0)).collect();
+            let mut counter = CellularAutomaton::new(|_: &usize, around: &[&usize]| around.len());
+            counter.neighbourhood = mode;
+            counter.outside = Some(0);
+            counter.step(&triangles, &mut layer);
+            *layer.get(&TriangleCoord::from_column(5, 3)).unwrap()
+        };
+        assert_eq!(count(TriangleNeighbours::Strict), 3);
+        assert_eq!(count(TriangleNeighbours::Expanded), 12);
+    }
+}
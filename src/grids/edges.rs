@@ -0,0 +1,471 @@
+use bevy::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::{
+    coordinates::{HexCoord, SquareCoord, SquareDirection, TriangleCoord},
+    primitives::{Hexes, Squares, Triangles},
+};
+
+/// Edge between two cells, addressed from the one cell owning it so that
+/// both sides agree on its coordinate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EdgeCoord<K> {
+    cell: K,
+    side: u8,
+}
+
+impl<K: Copy> EdgeCoord<K> {
+    pub fn cell(&self) -> K {
+        self.cell
+    }
+
+    pub fn side(&self) -> u8 {
+        self.side
+    }
+}
+
+/// Corner shared by several cells, addressed from the one cell owning it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VertexCoord<K> {
+    cell: K,
+    corner: u8,
+}
+
+impl<K: Copy> VertexCoord<K> {
+    pub fn cell(&self) -> K {
+        self.cell
+    }
+
+    pub fn corner(&self) -> u8 {
+        self.corner
+    }
+}
+
+fn nearest<T, F>(items: impl IntoIterator<Item = T>, pos: Vec3, to_vec3: F) -> T
+where
+    F: Fn(&T) -> Vec3,
+{
+    items
+        .into_iter()
+        .min_by(|a, b| {
+            let a = to_vec3(a).distance_squared(pos);
+            let b = to_vec3(b).distance_squared(pos);
+            a.total_cmp(&b)
+        })
+        .unwrap()
+}
+
+/// HEXAGON EDGES AND VERTICES
+impl HexCoord {
+    /// Edges in `HexCoord::DIRECTIONS` order
+    pub fn edges(&self) -> [EdgeCoord<HexCoord>; 6] {
+        std::array::from_fn(|i| EdgeCoord::<HexCoord>::new(*self, i as u8))
+    }
+
+    /// Vertices in `Hexes::corner_offset` order
+    pub fn vertices(&self) -> [VertexCoord<HexCoord>; 6] {
+        std::array::from_fn(|i| VertexCoord::<HexCoord>::new(*self, i as u8))
+    }
+}
+
+impl EdgeCoord<HexCoord> {
+    /// Edge facing `HexCoord::DIRECTIONS[side]`, owned by one of its first three sides
+    pub fn new(cell: HexCoord, side: u8) -> Self {
+        let side = side % 6;
+        if side < 3 {
+            EdgeCoord { cell, side }
+        } else {
+            EdgeCoord {
+                cell: cell + HexCoord::DIRECTIONS[side as usize],
+                side: side - 3,
+            }
+        }
+    }
+
+    pub fn between(a: &HexCoord, b: &HexCoord) -> Option<Self> {
+        HexCoord::DIRECTIONS
+            .iter()
+            .position(|d| *a + *d == *b)
+            .map(|side| EdgeCoord::<HexCoord>::new(*a, side as u8))
+    }
+
+    pub fn cells(&self) -> [HexCoord; 2] {
+        [
+            self.cell,
+            self.cell + HexCoord::DIRECTIONS[self.side as usize],
+        ]
+    }
+
+    pub fn vertices(&self) -> [VertexCoord<HexCoord>; 2] {
+        // side i runs from corner i - 1 to corner i
+        [
+            VertexCoord::<HexCoord>::new(self.cell, (self.side + 5) % 6),
+            VertexCoord::<HexCoord>::new(self.cell, self.side),
+        ]
+    }
+
+    /// Middle of the edge
+    pub fn to_vec3(&self, primitive: &Hexes) -> Vec3 {
+        let [a, b] = self.vertices();
+        (a.to_vec3(primitive) + b.to_vec3(primitive)) / 2.0
+    }
+
+    /// Edge closest to `pos`
+    pub fn new_from_world_pos(pos: Vec3, primitive: &Hexes) -> Self {
+        let cell = HexCoord::new_from_world_pos(pos, primitive);
+        nearest(cell.edges(), pos, |e| e.to_vec3(primitive))
+    }
+}
+
+impl VertexCoord<HexCoord> {
+    /// Corner between `HexCoord::DIRECTIONS[corner]` and the next direction,
+    /// owned by the cell for which it is corner 0 or 1
+    pub fn new(cell: HexCoord, corner: u8) -> Self {
+        let i = (corner % 6) as usize;
+        let d = HexCoord::DIRECTIONS;
+        [
+            (cell, i),
+            (cell + d[i], (i + 2) % 6),
+            (cell + d[(i + 1) % 6], (i + 4) % 6),
+        ]
+        .into_iter()
+        .find(|(_, corner)| *corner < 2)
+        .map(|(cell, corner)| VertexCoord {
+            cell,
+            corner: corner as u8,
+        })
+        .unwrap()
+    }
+
+    pub fn cells(&self) -> [HexCoord; 3] {
+        let i = self.corner as usize;
+        [
+            self.cell,
+            self.cell + HexCoord::DIRECTIONS[i],
+            self.cell + HexCoord::DIRECTIONS[i + 1],
+        ]
+    }
+
+    pub fn to_vec3(&self, primitive: &Hexes) -> Vec3 {
+        self.cell.to_vec3(primitive) + primitive.corner_offset(self.corner as usize)
+    }
+
+    /// Vertex closest to `pos`
+    pub fn new_from_world_pos(pos: Vec3, primitive: &Hexes) -> Self {
+        let cell = HexCoord::new_from_world_pos(pos, primitive);
+        nearest(cell.vertices(), pos, |v| v.to_vec3(primitive))
+    }
+}
+
+/// SQUARE EDGES AND VERTICES
+impl SquareCoord {
+    /// Edges in `SquareDirection::ORTHOGONAL` order
+    pub fn edges(&self) -> [EdgeCoord<SquareCoord>; 4] {
+        std::array::from_fn(|i| EdgeCoord::<SquareCoord>::new(*self, i as u8))
+    }
+
+    /// Vertices in `Squares::corner_offset` order
+    pub fn vertices(&self) -> [VertexCoord<SquareCoord>; 4] {
+        std::array::from_fn(|i| VertexCoord::<SquareCoord>::new(*self, i as u8))
+    }
+}
+
+impl EdgeCoord<SquareCoord> {
+    /// Edge facing `SquareDirection::ORTHOGONAL[side]`, owned by the cell to
+    /// its West or South
+    pub fn new(cell: SquareCoord, side: u8) -> Self {
+        let side = side % 4;
+        if side < 2 {
+            EdgeCoord { cell, side }
+        } else {
+            EdgeCoord {
+                cell: cell.neighbour(SquareDirection::ORTHOGONAL[side as usize]),
+                side: side - 2,
+            }
+        }
+    }
+
+    pub fn between(a: &SquareCoord, b: &SquareCoord) -> Option<Self> {
+        SquareDirection::ORTHOGONAL
+            .iter()
+            .position(|d| a.neighbour(*d) == *b)
+            .map(|side| EdgeCoord::<SquareCoord>::new(*a, side as u8))
+    }
+
+    pub fn cells(&self) -> [SquareCoord; 2] {
+        [
+            self.cell,
+            self.cell
+                .neighbour(SquareDirection::ORTHOGONAL[self.side as usize]),
+        ]
+    }
+
+    pub fn vertices(&self) -> [VertexCoord<SquareCoord>; 2] {
+        // side i runs from corner i - 1 to corner i
+        [
+            VertexCoord::<SquareCoord>::new(self.cell, (self.side + 3) % 4),
+            VertexCoord::<SquareCoord>::new(self.cell, self.side),
+        ]
+    }
+
+    /// Middle of the edge
+    pub fn to_vec3(&self, primitive: &Squares) -> Vec3 {
+        let [a, b] = self.vertices();
+        (a.to_vec3(primitive) + b.to_vec3(primitive)) / 2.0
+    }
+
+    /// Edge closest to `pos`
+    pub fn new_from_world_pos(pos: Vec3, primitive: &Squares) -> Self {
+        let cell = SquareCoord::new_from_world_pos(pos, primitive);
+        nearest(cell.edges(), pos, |e| e.to_vec3(primitive))
+    }
+}
+
+impl VertexCoord<SquareCoord> {
+    /// Corner counter-clockwise from North East, owned by the cell to its South West
+    pub fn new(cell: SquareCoord, corner: u8) -> Self {
+        let cell = match corner % 4 {
+            0 => cell,
+            1 => cell.neighbour(SquareDirection::West),
+            2 => cell.neighbour(SquareDirection::SouthWest),
+            _ => cell.neighbour(SquareDirection::South),
+        };
+        VertexCoord { cell, corner: 0 }
+    }
+
+    pub fn cells(&self) -> [SquareCoord; 4] {
+        [
+            self.cell,
+            self.cell.neighbour(SquareDirection::East),
+            self.cell.neighbour(SquareDirection::NorthEast),
+            self.cell.neighbour(SquareDirection::North),
+        ]
+    }
+
+    pub fn to_vec3(&self, primitive: &Squares) -> Vec3 {
+        self.cell.to_vec3(primitive) + primitive.corner_offset(self.corner as usize)
+    }
+
+    /// Vertex closest to `pos`
+    pub fn new_from_world_pos(pos: Vec3, primitive: &Squares) -> Self {
+        let cell = SquareCoord::new_from_world_pos(pos, primitive);
+        nearest(cell.vertices(), pos, |v| v.to_vec3(primitive))
+    }
+}
+
+/// TRIANGLE EDGES AND VERTICES
+impl TriangleCoord {
+    /// Edges in `TriangleCoord::directions` order
+    pub fn edges(&self) -> [EdgeCoord<TriangleCoord>; 3] {
+        std::array::from_fn(|i| EdgeCoord::<TriangleCoord>::new(*self, i as u8))
+    }
+
+    /// Vertices in `Triangles::corner_offset` order, rotated with the triangle
+    pub fn vertices(&self) -> [VertexCoord<TriangleCoord>; 3] {
+        std::array::from_fn(|i| VertexCoord::<TriangleCoord>::new(*self, i as u8))
+    }
+
+    /// Corner `i` as a column and a row boundary, row r lying between boundaries r and r + 1
+    fn corner_lattice_pos(&self, i: u8) -> (i32, i32) {
+        let (c, r) = (self.column(), self.r);
+        match (self.points_up(), i % 3) {
+            (true, 0) => (c, r + 1),
+            (true, 1) => (c - 1, r),
+            (true, _) => (c + 1, r),
+            (false, 0) => (c, r),
+            (false, 1) => (c + 1, r + 1),
+            (false, _) => (c - 1, r + 1),
+        }
+    }
+}
+
+impl EdgeCoord<TriangleCoord> {
+    /// Edge facing `cell.directions()[side]`, owned by the triangle pointing up
+    pub fn new(cell: TriangleCoord, side: u8) -> Self {
+        let side = side % 3;
+        if cell.points_up() {
+            return EdgeCoord { cell, side };
+        }
+        let dir = cell.directions()[side as usize];
+        let owner = cell.neighbour(dir).unwrap();
+        let side = owner
+            .directions()
+            .iter()
+            .position(|d| *d == dir.opposite())
+            .unwrap();
+        EdgeCoord {
+            cell: owner,
+            side: side as u8,
+        }
+    }
+
+    pub fn between(a: &TriangleCoord, b: &TriangleCoord) -> Option<Self> {
+        a.directions()
+            .iter()
+            .position(|d| a.neighbour(*d) == Some(*b))
+            .map(|side| EdgeCoord::<TriangleCoord>::new(*a, side as u8))
+    }
+
+    pub fn cells(&self) -> [TriangleCoord; 2] {
+        let dir = self.cell.directions()[self.side as usize];
+        [self.cell, self.cell.neighbour(dir).unwrap()]
+    }
+
+    pub fn vertices(&self) -> [VertexCoord<TriangleCoord>; 2] {
+        // NorthEast, NorthWest and South sides of a triangle pointing up
+        let (a, b) = match self.side {
+            0 => (0, 2),
+            1 => (0, 1),
+            _ => (1, 2),
+        };
+        [
+            VertexCoord::<TriangleCoord>::new(self.cell, a),
+            VertexCoord::<TriangleCoord>::new(self.cell, b),
+        ]
+    }
+
+    /// Middle of the edge
+    pub fn to_vec3(&self, primitive: &Triangles) -> Vec3 {
+        let [a, b] = self.vertices();
+        (a.to_vec3(primitive) + b.to_vec3(primitive)) / 2.0
+    }
+
+    /// Edge closest to `pos`
+    pub fn new_from_world_pos(pos: Vec3, primitive: &Triangles) -> Self {
+        let cell = TriangleCoord::new_from_world_pos(pos, primitive);
+        nearest(cell.edges(), pos, |e| e.to_vec3(primitive))
+    }
+}
+
+impl VertexCoord<TriangleCoord> {
+    /// Corner of `cell` as ordered by `Triangles::corner_offset` once rotated
+    /// with the triangle, owned by the triangle pointing up it is the bottom left of
+    pub fn new(cell: TriangleCoord, corner: u8) -> Self {
+        let (c, r) = cell.corner_lattice_pos(corner);
+        VertexCoord {
+            cell: TriangleCoord::from_column(c + 1, r),
+            corner: 1,
+        }
+    }
+
+    pub fn cells(&self) -> [TriangleCoord; 6] {
+        let (c, r) = self.cell.corner_lattice_pos(self.corner);
+        [
+            TriangleCoord::from_column(c - 1, r),
+            TriangleCoord::from_column(c, r),
+            TriangleCoord::from_column(c + 1, r),
+            TriangleCoord::from_column(c + 1, r - 1),
+            TriangleCoord::from_column(c, r - 1),
+            TriangleCoord::from_column(c - 1, r - 1),
+        ]
+    }
+
+    pub fn to_vec3(&self, primitive: &Triangles) -> Vec3 {
+        self.cell.to_vec3(primitive) + primitive.corner_offset(self.corner as usize)
+    }
+
+    /// Vertex closest to `pos`
+    pub fn new_from_world_pos(pos: Vec3, primitive: &Triangles) -> Self {
+        let cell = TriangleCoord::new_from_world_pos(pos, primitive);
+        nearest(cell.vertices(), pos, |v| v.to_vec3(primitive))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::{HexOrientation, TriangleNeighbours},
+        primitives::{GridAlign, GridWrap},
+    };
+
+    #[test]
+    fn hex_edges_and_vertices_are_shared() {
+        let primitive = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::FlatUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let cell = HexCoord { q: 2, r: -1 };
+        for (i, edge) in cell.edges().into_iter().enumerate() {
+            let other = cell + HexCoord::DIRECTIONS[i];
+            assert_eq!(EdgeCoord::<HexCoord>::between(&other, &cell), Some(edge));
+            assert!(edge.cells().contains(&other));
+            let mid = (cell.to_vec3(&primitive) + other.to_vec3(&primitive)) / 2.0;
+            assert!(edge.to_vec3(&primitive).distance(mid) < 1e-4);
+            assert_eq!(
+                EdgeCoord::<HexCoord>::new_from_world_pos(mid, &primitive),
+                edge
+            );
+        }
+        for (i, vertex) in cell.vertices().into_iter().enumerate() {
+            let corner = cell.to_vec3(&primitive) + primitive.corner_offset(i);
+            assert!(vertex.to_vec3(&primitive).distance(corner) < 1e-4);
+            for c in vertex.cells() {
+                assert!(c.vertices().contains(&vertex));
+            }
+        }
+    }
+
+    #[test]
+    fn square_edges_and_vertices_are_shared() {
+        let primitive = Squares {
+            size: 1.0,
+            alignment: GridAlign::XY,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let cell = SquareCoord { q: -1, r: 3 };
+        for (i, edge) in cell.edges().into_iter().enumerate() {
+            let other = cell.neighbour(SquareDirection::ORTHOGONAL[i]);
+            assert_eq!(EdgeCoord::<SquareCoord>::between(&other, &cell), Some(edge));
+            let [a, b] = edge.vertices();
+            assert!(cell.vertices().contains(&a) && other.vertices().contains(&b));
+        }
+        for (i, vertex) in cell.vertices().into_iter().enumerate() {
+            let corner = cell.to_vec3(&primitive) + primitive.corner_offset(i);
+            assert!(vertex.to_vec3(&primitive).distance(corner) < 1e-4);
+            assert_eq!(
+                VertexCoord::<SquareCoord>::new_from_world_pos(corner, &primitive),
+                vertex
+            );
+        }
+    }
+
+    #[test]
+    fn triangle_edges_and_vertices_are_shared() {
+        let primitive = Triangles {
+            size: 1.0,
+            alignment: GridAlign::XY,
+            neighbors: TriangleNeighbours::Strict,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        for cell in [
+            TriangleCoord::new(1, 1, false),
+            TriangleCoord::new(1, 2, true),
+        ] {
+            for (i, edge) in cell.edges().into_iter().enumerate() {
+                let other = cell.neighbour(cell.directions()[i]).unwrap();
+                assert_eq!(
+                    EdgeCoord::<TriangleCoord>::between(&other, &cell),
+                    Some(edge)
+                );
+                for v in edge.vertices() {
+                    assert!(cell.vertices().contains(&v) && other.vertices().contains(&v));
+                }
+            }
+            let rotation = cell.to_world_pos(primitive).rotation;
+            for (i, vertex) in cell.vertices().into_iter().enumerate() {
+                let corner = cell.to_vec3(&primitive) + rotation * primitive.corner_offset(i);
+                assert!(vertex.to_vec3(&primitive).distance(corner) < 1e-4);
+                assert!(vertex.cells().contains(&cell));
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use bevy::prelude::*;
+use std::{fmt::Display, fs, io, path::Path};
+
+use super::{layers::GridLayer, primitives::GridAlign, GridLayout};
+
+/// How grids are drawn to pictures
+#[derive(Clone, Debug)]
+pub struct ExportOptions {
+    /// Pixels per world unit
+    pub scale: f32,
+    /// Pixels around the cells
+    pub margin: f32,
+    pub background: Option<Color>,
+    pub stroke: Option<Color>,
+    pub stroke_width: f32,
+    /// Writes the coordinates of every cell on it, in SVG pictures only
+    pub labels: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            scale: 64.0,
+            margin: 8.0,
+            background: Some(Color::WHITE),
+            stroke: Some(Color::BLACK),
+            stroke_width: 1.0,
+            labels: false,
+        }
+    }
+}
+
+/// Cell polygon in pixels
+struct Shape {
+    points: Vec<Vec2>,
+    centre: Vec2,
+    color: Color,
+    label: String,
+}
+
+/// Polygons of the coloured cells, top to bottom and left to right, and the
+/// size of the picture
+fn layout<T>(
+    grid: &T,
+    fill: &GridLayer<T::Coord, Color>,
+    options: &ExportOptions,
+) -> (Vec<Shape>, UVec2)
+where
+    T: GridLayout,
+    T::Coord: Display,
+{
+    // Picture space: x to the right, y down, North up
+    let alignment = grid.alignment();
+    let project = |pos: Vec3| match alignment {
+        GridAlign::XY => Vec2::new(pos.x, -pos.y),
+        GridAlign::XZ => Vec2::new(pos.x, pos.z),
+    };
+    let mut shapes: Vec<Shape> = fill
+        .iter()
+        .map(|(cell, color)| {
+            let points: Vec<Vec2> = grid
+                .cell_corners(cell)
+                .into_iter()
+                .map(|corner| project(corner) * options.scale)
+                .collect();
+            Shape {
+                centre: points.iter().sum::<Vec2>() / points.len() as f32,
+                points,
+                color: *color,
+                label: cell.to_string(),
+            }
+        })
+        .collect();
+    let (min, max) = shapes.iter().flat_map(|s| s.points.iter()).fold(
+        (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+        |(min, max), p| (min.min(*p), max.max(*p)),
+    );
+    if shapes.is_empty() {
+        return (shapes, UVec2::splat((2.0 * options.margin).ceil() as u32));
+    }
+    let origin = min - Vec2::splat(options.margin);
+    for shape in shapes.iter_mut() {
+        shape.centre -= origin;
+        shape.points.iter_mut().for_each(|p| *p -= origin);
+    }
+    shapes.sort_by(|a, b| {
+        (a.centre.y, a.centre.x)
+            .partial_cmp(&(b.centre.y, b.centre.x))
+            .unwrap()
+    });
+    let size = (max - min + Vec2::splat(2.0 * options.margin)).ceil();
+    (shapes, size.as_uvec2())
+}
+
+fn hex(color: Color) -> String {
+    let [r, g, b, _] = color.as_rgba_f32().map(|c| (c * 255.0).round() as u8);
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Colour attributes of an SVG element
+fn paint(attribute: &str, color: Color) -> String {
+    match color.a() {
+        a if a < 1.0 => format!(r#"{attribute}="{}" {attribute}-opacity="{a}""#, hex(color)),
+        _ => format!(r#"{attribute}="{}""#, hex(color)),
+    }
+}
+
+/// SVG picture of the cells of `fill`, each in its colour
+pub fn to_svg<T>(grid: &T, fill: &GridLayer<T::Coord, Color>, options: &ExportOptions) -> String
+where
+    T: GridLayout,
+    T::Coord: Display,
+{
+    let (shapes, size) = layout(grid, fill, options);
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+        size.x, size.y
+    );
+    svg.push('\n');
+    if let Some(background) = options.background {
+        svg += &format!(
+            "<rect width=\"100%\" height=\"100%\" {}/>\n",
+            paint("fill", background)
+        );
+    }
+    let stroke = options
+        .stroke
+        .map_or("stroke=\"none\"".to_string(), |color| {
+            format!(
+                r#"{} stroke-width="{}" stroke-linejoin="round""#,
+                paint("stroke", color),
+                options.stroke_width
+            )
+        });
+    for shape in &shapes {
+        let points: Vec<String> = shape
+            .points
+            .iter()
+            .map(|p| format!("{:.2},{:.2}", p.x, p.y))
+            .collect();
+        svg += &format!(
+            "<polygon points=\"{}\" {} {stroke}/>\n",
+            points.join(" "),
+            paint("fill", shape.color)
+        );
+    }
+    if options.labels {
+        let font_size = 0.2 * options.scale;
+        for shape in &shapes {
+            svg += &format!(
+                r#"<text x="{:.2}" y="{:.2}" font-size="{font_size}" text-anchor="middle" dominant-baseline="middle">{}</text>"#,
+                shape.centre.x, shape.centre.y, shape.label
+            );
+            svg.push('\n');
+        }
+    }
+    svg += "</svg>\n";
+    svg
+}
+
+/// Pixels of the cells of `fill`, each in its colour, as RGBA rows
+pub fn rasterize<T>(
+    grid: &T,
+    fill: &GridLayer<T::Coord, Color>,
+    options: &ExportOptions,
+) -> (UVec2, Vec<[u8; 4]>)
+where
+    T: GridLayout,
+    T::Coord: Display,
+{
+    let (shapes, size) = layout(grid, fill, options);
+    let background = options.background.unwrap_or(Color::NONE).as_rgba_f32();
+    let mut pixels = vec![background; (size.x * size.y) as usize];
+    // Samples per pixel, for anti-aliased edges
+    const SAMPLES: [Vec2; 4] = [
+        Vec2::new(0.25, 0.25),
+        Vec2::new(0.75, 0.25),
+        Vec2::new(0.25, 0.75),
+        Vec2::new(0.75, 0.75),
+    ];
+    let half_stroke = 0.5 * options.stroke_width;
+    for shape in &shapes {
+        let edges: Vec<(Vec2, Vec2)> = shape
+            .points
+            .iter()
+            .zip(shape.points.iter().cycle().skip(1))
+            .map(|(a, b)| (*a, *b))
+            .collect();
+        let (min, max) = shape.points.iter().fold(
+            (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+            |(min, max), p| (min.min(*p), max.max(*p)),
+        );
+        let min = (min - half_stroke).floor().max(Vec2::ZERO).as_uvec2();
+        let max = (max + half_stroke).ceil().as_uvec2().min(size);
+        for y in min.y..max.y {
+            for x in min.x..max.x {
+                let pixel = Vec2::new(x as f32, y as f32);
+                let (mut filled, mut stroked) = (0.0, 0.0);
+                for sample in SAMPLES.map(|s| pixel + s) {
+                    if options.stroke.is_some()
+                        && edges
+                            .iter()
+                            .any(|(a, b)| segment_distance(sample, *a, *b) <= half_stroke)
+                    {
+                        stroked += 0.25;
+                    } else if inside(sample, &edges) {
+                        filled += 0.25;
+                    }
+                }
+                let pixel = &mut pixels[(y * size.x + x) as usize];
+                blend(pixel, shape.color, filled);
+                if let Some(stroke) = options.stroke {
+                    blend(pixel, stroke, stroked);
+                }
+            }
+        }
+    }
+    let pixels = pixels
+        .into_iter()
+        .map(|p| p.map(|c| (c * 255.0).round() as u8))
+        .collect();
+    (size, pixels)
+}
+
+/// Whether a point lies within a convex polygon, whatever its winding
+fn inside(point: Vec2, edges: &[(Vec2, Vec2)]) -> bool {
+    let sides = edges.iter().map(|(a, b)| (*b - *a).perp_dot(point - *a));
+    let (mut left, mut right) = (false, false);
+    for side in sides {
+        left |= side > 0.0;
+        right |= side < 0.0;
+    }
+    !(left && right)
+}
+
+fn segment_distance(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = ((point - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0);
+    point.distance(a + t * ab)
+}
+
+/// Paints `color` over `pixel`, covering the given fraction of it
+fn blend(pixel: &mut [f32; 4], color: Color, coverage: f32) {
+    let [r, g, b, a] = color.as_rgba_f32();
+    let alpha = a * coverage;
+    let out = alpha + pixel[3] * (1.0 - alpha);
+    if out <= 0.0 {
+        return;
+    }
+    let below = pixel[3] * (1.0 - alpha);
+    for (channel, c) in pixel.iter_mut().zip([r, g, b]) {
+        *channel = (c * alpha + *channel * below) / out;
+    }
+    pixel[3] = out;
+}
+
+/// PNG picture of the cells of `fill`, each in its colour
+pub fn to_png<T>(
+    grid: &T,
+    fill: &GridLayer<T::Coord, Color>,
+    options: &ExportOptions,
+) -> io::Result<Vec<u8>>
+where
+    T: GridLayout,
+    T::Coord: Display,
+{
+    let (size, pixels) = rasterize(grid, fill, options);
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, size.x, size.y);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels.concat())?;
+    writer.finish()?;
+    Ok(png)
+}
+
+/// Writes an SVG picture to `.svg` files and a PNG one to any other
+pub fn save_picture<T>(
+    path: impl AsRef<Path>,
+    grid: &T,
+    fill: &GridLayer<T::Coord, Color>,
+    options: &ExportOptions,
+) -> io::Result<()>
+where
+    T: GridLayout,
+    T::Coord: Display,
+{
+    let bytes = if path.as_ref().extension().is_some_and(|e| e == "svg") {
+        to_svg(grid, fill, options).into_bytes()
+    } else {
+        to_png(grid, fill, options)?
+    };
+    fs::write(path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::{HexCoord, HexOrientation, SquareCoord},
+        primitives::{GridWrap, Hexes, Squares},
+    };
+
+    #[test]
+    fn svg_pictures() {
+        let hexes = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::PointyUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let fill = [HexCoord { q: 0, r: 0 }, HexCoord { q: 1, r: 0 }]
+            .into_iter()
+            .zip([Color::RED, Color::rgba(0.0, 0.0, 1.0, 0.5)])
+            .collect();
+        let options = ExportOptions {
+            labels: true,
+            ..default()
+        };
+        let svg = to_svg(&hexes, &fill, &options);
+        assert_eq!(svg.matches("<polygon").count(), 2);
+        assert!(svg.contains(r##"fill="#ff0000""##));
+        assert!(svg.contains(r#"fill-opacity="0.5""#));
+        assert!(svg.contains(">1,0</text>"));
+        // Two pointy hexes side by side, √3 wide each
+        let width = (2.0 * 3_f32.sqrt() * 64.0 + 16.0).ceil();
+        assert!(svg.contains(&format!(r#"width="{width}" height="144""#)));
+    }
+
+    #[test]
+    fn png_pictures() {
+        let squares = Squares {
+            size: 1.0,
+            alignment: GridAlign::XY,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let fill = [(SquareCoord { q: 0, r: 0 }, Color::RED)]
+            .into_iter()
+            .chain([(SquareCoord { q: 0, r: 1 }, Color::BLUE)])
+            .collect();
+        let options = ExportOptions {
+            scale: 10.0,
+            margin: 0.0,
+            stroke: None,
+            ..default()
+        };
+        let (size, pixels) = rasterize(&squares, &fill, &options);
+        // Squares are √2 size wide
+        assert_eq!(size, UVec2::new(15, 29));
+        // North is up
+        assert_eq!(pixels[(3 * size.x + 7) as usize], [0, 0, 255, 255]);
+        assert_eq!(pixels[(25 * size.x + 7) as usize], [255, 0, 0, 255]);
+        assert!(to_png(&squares, &fill, &options)
+            .unwrap()
+            .starts_with(b"\x89PNG"));
+    }
+}
@@ -0,0 +1,366 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use std::{collections::BinaryHeap, hash::Hash, marker::PhantomData};
+
+use super::{
+    coordinates::Coords,
+    layers::GridLayer,
+    pathfinding::{Path, Step},
+    terrain::PathCosts,
+    topology::Topology,
+    units::{move_units, MoveAlong, MoveSpeed, DEFAULT_SPEED},
+    GridConfig, GridLayout, GridPosition,
+};
+
+/// Way to the nearest of a set of goals from every cell at once, for crowds
+/// to share rather than each searching for a path of its own
+#[derive(Clone)]
+pub struct FlowField<K: Coords> {
+    goals: Vec<K>,
+    /// Cost of walking to the nearest goal
+    integration: GridLayer<K, u32>,
+    /// Neighbour to step on towards it
+    next: GridLayer<K, K>,
+}
+
+impl<K: Coords + Copy + Eq + Hash> FlowField<K> {
+    /// Field towards the `goals`, `cost` being as for `find_path`
+    pub fn new<T, F>(grid: &T, goals: Vec<K>, cost: F) -> Self
+    where
+        T: Topology<K>,
+        F: Fn(&K) -> Option<u32>,
+    {
+        let mut integration = GridLayer::new();
+        let mut next = GridLayer::new();
+        let mut open = BinaryHeap::new();
+        for goal in &goals {
+            let goal = grid.normalize(goal);
+            if cost(&goal).is_some() && integration.insert(goal, 0).is_none() {
+                open.push(Step {
+                    estimate: 0,
+                    cell: goal,
+                });
+            }
+        }
+        // Dijkstra search backwards from the goals, paying for the cells
+        // walked onto rather than those walked off
+        while let Some(Step { estimate, cell }) = open.pop() {
+            if integration.get(&cell) != Some(&estimate) {
+                continue;
+            }
+            let Some(step) = cost(&cell) else {
+                continue;
+            };
+            for n in grid.neighbours(&cell) {
+                if cost(&n).is_none() {
+                    continue;
+                }
+                let total = estimate + step;
+                if integration.get(&n).is_none_or(|d| total < *d) {
+                    integration.insert(n, total);
+                    next.insert(n, cell);
+                    open.push(Step {
+                        estimate: total,
+                        cell: n,
+                    });
+                }
+            }
+        }
+        FlowField {
+            goals,
+            integration,
+            next,
+        }
+    }
+
+    pub fn goals(&self) -> &[K] {
+        &self.goals
+    }
+
+    /// Cost of walking from the cell to the nearest goal, `None` if no goal
+    /// can be reached from it
+    pub fn cost(&self, coord: &K) -> Option<u32> {
+        self.integration.get(coord).copied()
+    }
+
+    /// Cell to step on from `coord`, `None` on a goal or cut off from them
+    pub fn next(&self, coord: &K) -> Option<K> {
+        self.next.get(coord).copied()
+    }
+
+    pub fn integration(&self) -> &GridLayer<K, u32> {
+        &self.integration
+    }
+
+    pub fn directions(&self) -> &GridLayer<K, K> {
+        &self.next
+    }
+}
+
+/// Flow fields built for the goals units follow, dropped when costs change.
+/// Fields are shared by every order and repetition of the same goals.
+#[derive(Resource)]
+pub struct FlowFields<K: Coords> {
+    fields: HashMap<Vec<K>, FlowField<K>>,
+}
+
+impl<K: Coords + Copy + Eq + Hash> Default for FlowFields<K> {
+    fn default() -> Self {
+        FlowFields {
+            fields: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Coords + Copy + Ord + Hash> FlowFields<K> {
+    /// Goals sorted and without repeats, the fields are cached under
+    fn key(goals: &[K]) -> Vec<K> {
+        let mut key = goals.to_vec();
+        key.sort_unstable();
+        key.dedup();
+        key
+    }
+
+    pub fn get(&self, goals: &[K]) -> Option<&FlowField<K>> {
+        self.fields.get(&Self::key(goals))
+    }
+
+    /// Field towards the `goals`, built unless cached
+    pub fn get_or_build<T, F>(&mut self, grid: &T, goals: &[K], cost: F) -> &FlowField<K>
+    where
+        T: Topology<K>,
+        F: Fn(&K) -> Option<u32>,
+    {
+        let key = Self::key(goals);
+        self.fields
+            .entry(key)
+            .or_insert_with_key(|key| FlowField::new(grid, key.clone(), cost))
+    }
+
+    /// Drops the fields towards goals no longer among `in_use`
+    pub fn retain_goals<'a>(&mut self, in_use: impl IntoIterator<Item = &'a [K]>)
+    where
+        K: 'a,
+    {
+        let in_use: HashSet<Vec<K>> = in_use.into_iter().map(Self::key).collect();
+        self.fields.retain(|goals, _| in_use.contains(goals));
+    }
+
+    pub fn invalidate(&mut self) {
+        self.fields.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+/// Walks a unit to the nearest of the `goals` down their `FlowField`, at its
+/// `MoveSpeed`. Removed once the unit stands on a goal.
+#[derive(Component, Clone, Debug)]
+pub struct FollowFlow<K> {
+    pub goals: Vec<K>,
+}
+
+/// Crowds of units on the grid of `T` following flow fields, by way of the
+/// `UnitPlugin`
+pub struct FlowFieldPlugin<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for FlowFieldPlugin<T> {
+    fn default() -> Self {
+        FlowFieldPlugin(PhantomData)
+    }
+}
+
+impl<T: GridLayout> Plugin for FlowFieldPlugin<T>
+where
+    T::Coord: Ord,
+{
+    fn build(&self, app: &mut App) {
+        app.insert_resource(FlowFields::<T::Coord>::default())
+            .add_system(
+                steer_units::<T>
+                    .before(move_units::<T>)
+                    .run_if(resource_exists::<GridConfig<T>>()),
+            );
+    }
+}
+
+/// Units following a flow, with where they stand and how fast they walk
+type Followers<'a, K> = (
+    Entity,
+    &'a GridPosition<K>,
+    &'a FollowFlow<K>,
+    Option<&'a MoveSpeed>,
+    Option<&'a mut MoveAlong<K>>,
+);
+
+/// Keeps units following a flow walking one cell ahead of where they are,
+/// dropping the fields none of them follow any more
+pub fn steer_units<T: GridLayout>(
+    mut commands: Commands,
+    grid: Res<GridConfig<T>>,
+    costs: PathCosts<T::Coord>,
+    mut fields: ResMut<FlowFields<T::Coord>>,
+    mut units: Query<Followers<T::Coord>>,
+) where
+    T::Coord: Ord,
+{
+    if costs.is_changed() {
+        fields.invalidate();
+    }
+    for (unit, position, follow, speed, walk) in units.iter_mut() {
+        let field = fields.get_or_build(&grid.0, &follow.goals, |c| costs.cost(c));
+        match walk {
+            Some(mut walk) => {
+                if let [last] = walk.remaining() {
+                    if let Some(next) = field.next(last) {
+                        walk.push(next);
+                    }
+                }
+            }
+            None => {
+                let from = grid.0.normalize(&position.0);
+                if let Some(next) = field.next(&from) {
+                    let path = Path {
+                        cells: vec![from, next],
+                        cost: field.cost(&from).unwrap_or(0) - field.cost(&next).unwrap_or(0),
+                    };
+                    let speed = speed.map_or(DEFAULT_SPEED, |s| s.0);
+                    commands.entity(unit).insert(MoveAlong::new(path, speed));
+                } else if field.cost(&from) == Some(0) {
+                    commands.entity(unit).remove::<FollowFlow<T::Coord>>();
+                }
+            }
+        }
+    }
+    fields.retain_goals(
+        units
+            .iter()
+            .map(|(_, _, follow, _, _)| follow.goals.as_slice()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::{HexCoord, HexOrientation, SquareCoord},
+        primitives::{GridAlign, GridWrap, Hexes, Squares},
+        testing::{spawn_map, square, tick},
+        units::{Occupancy, UnitPlugin},
+        GridMap, GridPlugin,
+    };
+    use bevy::utils::Instant;
+
+    #[test]
+    fn fields_lead_down_to_the_goals() {
+        let hexes = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::PointyUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let hex = |q, r| HexCoord { q, r };
+        let in_map = |c: &HexCoord| c.distance(&HexCoord::ZERO) <= 5;
+        let swamp = |c: &HexCoord| c.q == 0 && c.r > -3;
+        let cost = |c: &HexCoord| in_map(c).then_some(if swamp(c) { 5 } else { 1 });
+        let goals = vec![hex(0, 5), hex(-5, 5)];
+        let field = FlowField::new(&hexes, goals, cost);
+
+        assert_eq!(field.integration().len(), 91);
+        for (cell, d) in field.integration().iter() {
+            let Some(next) = field.next(cell) else {
+                assert_eq!(*d, 0);
+                continue;
+            };
+            assert_eq!(cell.distance(&next), 1);
+            assert_eq!(field.cost(&next).unwrap() + cost(&next).unwrap(), *d);
+        }
+        // from anywhere down to a goal
+        let mut cell = hex(5, -5);
+        let mut spent = 0;
+        while let Some(next) = field.next(&cell) {
+            spent += cost(&next).unwrap();
+            cell = next;
+        }
+        assert!(field.goals().contains(&cell));
+        assert_eq!(spent, field.cost(&hex(5, -5)).unwrap());
+    }
+
+    #[test]
+    fn crowds_walk_to_the_goals() {
+        let squares = Squares {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let mut app = App::new();
+        app.insert_resource(Time::new(Instant::now()))
+            .add_plugin(GridPlugin::new(squares))
+            .add_plugin(UnitPlugin::<Squares>::default())
+            .add_plugin(FlowFieldPlugin::<Squares>::default())
+            .add_startup_system(spawn_map::<Squares>(8, 8));
+        let goals = vec![square(7, 7), square(7, 6)];
+        // the same goals, in another order and repeated, share one field
+        let shuffled = vec![square(7, 6), square(7, 7), square(7, 6)];
+        let units: Vec<Entity> = [(square(0, 0), &goals), (square(0, 7), &shuffled)]
+            .into_iter()
+            .map(|(cell, goals)| {
+                let follow = FollowFlow {
+                    goals: goals.clone(),
+                };
+                app.world
+                    .spawn((
+                        GridPosition(cell),
+                        TransformBundle::from_transform(squares.cell_transform(&cell)),
+                        follow,
+                    ))
+                    .id()
+            })
+            .collect();
+        tick(&mut app, 0.0);
+        assert_eq!(app.world.resource::<FlowFields<SquareCoord>>().len(), 1);
+
+        for _ in 0..40 {
+            tick(&mut app, 0.25);
+        }
+        let occupancy = app.world.resource::<Occupancy<SquareCoord>>();
+        for unit in units {
+            assert!(goals.contains(&occupancy.cell_of(unit).unwrap()));
+            assert!(app.world.get::<FollowFlow<SquareCoord>>(unit).is_none());
+        }
+        // nobody follows the field any more
+        assert!(app.world.resource::<FlowFields<SquareCoord>>().is_empty());
+
+        // a cell taken off the map throws the fields away
+        let cell = square(0, 0);
+        app.world.spawn((
+            GridPosition(cell),
+            TransformBundle::from_transform(squares.cell_transform(&cell)),
+            FollowFlow {
+                goals: goals.clone(),
+            },
+        ));
+        tick(&mut app, 0.0);
+        let fields = app.world.resource::<FlowFields<SquareCoord>>();
+        assert!(fields.get(&shuffled).unwrap().cost(&square(3, 3)).is_some());
+        let cell = app
+            .world
+            .resource_mut::<GridMap<SquareCoord>>()
+            .remove(&square(3, 3));
+        app.world.despawn(cell.unwrap());
+        tick(&mut app, 0.0);
+        let fields = app.world.resource::<FlowFields<SquareCoord>>();
+        assert_eq!(fields.len(), 1);
+        assert!(fields.get(&goals).unwrap().cost(&square(3, 3)).is_none());
+    }
+}
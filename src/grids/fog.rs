@@ -0,0 +1,238 @@
+use bevy::{prelude::*, utils::HashMap};
+use std::{hash::Hash, marker::PhantomData};
+
+use super::{
+    coordinates::Coords,
+    layers::GridLayer,
+    terrain::Terrains,
+    visibility::{FieldOfView, Opaque},
+    GridPosition,
+};
+#[cfg(feature = "render")]
+use super::{CellColor, GridMap};
+
+/// Side a unit plays for, each one with its own fog of war
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Faction(pub u32);
+
+/// How many cells away a unit sees
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Vision {
+    pub radius: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FogState {
+    /// Never seen
+    #[default]
+    Hidden,
+    /// Seen before, but not in sight of any unit
+    Explored,
+    /// In sight of at least one unit
+    Visible,
+}
+
+/// Fog state of every cell, per faction
+#[derive(Resource)]
+pub struct FogOfWar<K: Coords> {
+    factions: HashMap<Faction, GridLayer<K, FogState>>,
+}
+
+impl<K: Coords + Eq + Hash> FogOfWar<K> {
+    pub fn state(&self, faction: &Faction, coord: &K) -> FogState {
+        self.factions
+            .get(faction)
+            .and_then(|layer| layer.get(coord))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn layer(&self, faction: &Faction) -> Option<&GridLayer<K, FogState>> {
+        self.factions.get(faction)
+    }
+
+    /// Turns everything `faction` sees into explored cells, ready to be seen again
+    fn cover(&mut self, faction: Faction) -> &mut GridLayer<K, FogState> {
+        let layer = self.factions.entry(faction).or_default();
+        for (_, state) in layer.iter_mut() {
+            if *state == FogState::Visible {
+                *state = FogState::Explored;
+            }
+        }
+        layer
+    }
+}
+
+impl<K: Coords> Default for FogOfWar<K> {
+    fn default() -> Self {
+        FogOfWar {
+            factions: HashMap::new(),
+        }
+    }
+}
+
+/// Faction whose fog of war is drawn on the grid
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct FogView(pub Faction);
+
+/// Keeps a `FogOfWar<K>` up to date with the units carrying a `Vision`. The
+/// `GridRenderPlugin` draws it for the `FogView` faction.
+pub struct FogOfWarPlugin<K>(PhantomData<fn() -> K>);
+
+impl<K> Default for FogOfWarPlugin<K> {
+    fn default() -> Self {
+        FogOfWarPlugin(PhantomData)
+    }
+}
+
+impl<K: FieldOfView + Send + Sync + 'static> Plugin for FogOfWarPlugin<K> {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(FogOfWar::<K>::default())
+            .add_system(update_fog::<K>);
+    }
+}
+
+/// Brightness of explored cells out of sight
+#[cfg(feature = "render")]
+const EXPLORED_BRIGHTNESS: f32 = 0.35;
+
+/// Viewers whose sight may have changed
+type Moved<K> = (
+    With<Vision>,
+    Or<(Changed<GridPosition<K>>, Changed<Vision>, Changed<Faction>)>,
+);
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_fog<K: FieldOfView + Send + Sync + 'static>(
+    mut fog: ResMut<FogOfWar<K>>,
+    opaque: Option<Res<GridLayer<K, Opaque>>>,
+    terrains: Terrains<K>,
+    moved: Query<(Entity, &Faction), Moved<K>>,
+    mut blinded: RemovedComponents<Vision>,
+    mut unplaced: RemovedComponents<GridPosition<K>>,
+    mut defected: RemovedComponents<Faction>,
+    viewers: Query<(&GridPosition<K>, &Vision, &Faction)>,
+    mut sides: Local<HashMap<Entity, Faction>>,
+) {
+    let mut factions = Vec::new();
+    // viewers gone, and the factions they last saw for
+    for viewer in blinded.iter().chain(unplaced.iter()).chain(defected.iter()) {
+        factions.extend(sides.remove(&viewer));
+    }
+    for (viewer, faction) in moved.iter() {
+        factions.push(*faction);
+        factions.extend(sides.insert(viewer, *faction));
+    }
+    // without knowing what became opaque, everyone looks again
+    if opaque.as_ref().is_some_and(|o| o.is_changed()) || terrains.is_changed() {
+        factions.extend(fog.factions.keys().copied());
+        factions.extend(viewers.iter().map(|(_, _, f)| *f));
+    }
+    factions.sort_by_key(|f| f.0);
+    factions.dedup();
+
+    let is_opaque = |coord: &K| {
+        opaque.as_ref().is_some_and(|o| o.contains(coord)) || terrains.blocks_vision(coord)
+    };
+    for faction in factions {
+        let layer = fog.cover(faction);
+        for (position, vision, _) in viewers.iter().filter(|(_, _, f)| **f == faction) {
+            for coord in position.0.field_of_view(vision.radius, is_opaque) {
+                layer.insert(coord, FogState::Visible);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "render")]
+pub fn render_fog<K: Coords + Eq + Hash + Send + Sync + 'static>(
+    fog: Res<FogOfWar<K>>,
+    view: Option<Res<FogView>>,
+    map: Res<GridMap<K>>,
+    mut cells: Query<(&CellColor, &Handle<StandardMaterial>, &mut Visibility)>,
+    recolored: Query<(), Changed<CellColor>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let Some(view) = view else {
+        return;
+    };
+    if !fog.is_changed() && !view.is_changed() && !map.is_changed() && recolored.is_empty() {
+        return;
+    }
+    for (coord, entity) in map.iter() {
+        let Ok((color, handle, mut visibility)) = cells.get_mut(*entity) else {
+            continue;
+        };
+        let brightness = match fog.state(&view.0, coord) {
+            FogState::Hidden => {
+                *visibility = Visibility::Hidden;
+                continue;
+            }
+            FogState::Explored => EXPLORED_BRIGHTNESS,
+            FogState::Visible => 1.0,
+        };
+        *visibility = Visibility::Inherited;
+        if let Some(material) = materials.get_mut(handle) {
+            let [r, g, b, a] = color.0.as_rgba_f32();
+            material.base_color = Color::rgba(r * brightness, g * brightness, b * brightness, a);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::coordinates::HexCoord;
+
+    #[test]
+    fn factions_have_their_own_fog() {
+        let mut app = App::new();
+        app.add_system(update_fog::<HexCoord>)
+            .insert_resource(FogOfWar::<HexCoord>::default());
+        let (red, blue) = (Faction(0), Faction(1));
+        let scout = app
+            .world
+            .spawn((GridPosition(HexCoord::ZERO), Vision { radius: 2 }, red))
+            .id();
+        app.update();
+
+        let far = HexCoord { q: 5, r: 0 };
+        let fog = app.world.resource::<FogOfWar<HexCoord>>();
+        assert_eq!(fog.state(&red, &HexCoord::ZERO), FogState::Visible);
+        assert_eq!(fog.state(&red, &far), FogState::Hidden);
+        assert_eq!(fog.state(&blue, &HexCoord::ZERO), FogState::Hidden);
+
+        app.world.entity_mut(scout).insert(GridPosition(far));
+        app.update();
+        let fog = app.world.resource::<FogOfWar<HexCoord>>();
+        assert_eq!(fog.state(&red, &HexCoord::ZERO), FogState::Explored);
+        assert_eq!(fog.state(&red, &far), FogState::Visible);
+    }
+
+    #[test]
+    fn defectors_leave_their_old_side_in_the_dark() {
+        let mut app = App::new();
+        app.add_system(update_fog::<HexCoord>)
+            .insert_resource(FogOfWar::<HexCoord>::default());
+        let (red, blue) = (Faction(0), Faction(1));
+        let scout = app
+            .world
+            .spawn((GridPosition(HexCoord::ZERO), Vision { radius: 2 }, red))
+            .id();
+        app.update();
+
+        app.world.entity_mut(scout).insert(blue);
+        app.update();
+        let fog = app.world.resource::<FogOfWar<HexCoord>>();
+        assert_eq!(fog.state(&red, &HexCoord::ZERO), FogState::Explored);
+        assert_eq!(fog.state(&blue, &HexCoord::ZERO), FogState::Visible);
+
+        // taken off the grid, the scout sees nothing
+        app.world
+            .entity_mut(scout)
+            .remove::<GridPosition<HexCoord>>();
+        app.update();
+        let fog = app.world.resource::<FogOfWar<HexCoord>>();
+        assert_eq!(fog.state(&blue, &HexCoord::ZERO), FogState::Explored);
+    }
+}
@@ -0,0 +1,394 @@
+use bevy::prelude::*;
+use std::hash::Hash;
+
+use super::{
+    coordinates::Coords,
+    layers::GridLayer,
+    terrain::{TerrainId, TerrainRegistry, UnknownTerrain},
+    GridLayout,
+};
+
+/// Deterministic 64 bit mix of a value, the output function of SplitMix64
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Random value of a lattice point, the same for the same seed on every platform
+fn hash(seed: u64, x: i32, y: i32) -> u64 {
+    mix(seed ^ mix(((x as u32 as u64) << 32) | y as u32 as u64))
+}
+
+/// Between -1 and 1
+fn unit(hash: u64) -> f32 {
+    (hash >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+/// Dot product of an offset with one of 8 gradients picked by `hash`
+fn gradient(hash: u64, offset: Vec2) -> f32 {
+    const GRADIENTS: [Vec2; 8] = [
+        Vec2::new(1.0, 0.0),
+        Vec2::new(-1.0, 0.0),
+        Vec2::new(0.0, 1.0),
+        Vec2::new(0.0, -1.0),
+        Vec2::new(0.70710677, 0.70710677),
+        Vec2::new(-0.70710677, 0.70710677),
+        Vec2::new(0.70710677, -0.70710677),
+        Vec2::new(-0.70710677, -0.70710677),
+    ];
+    GRADIENTS[(hash & 7) as usize].dot(offset)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NoiseKind {
+    /// Random values at lattice points, smoothly blended
+    Value,
+    /// Random gradients at lattice points
+    #[default]
+    Perlin,
+    /// Random gradients at the corners of a triangular lattice, with fewer
+    /// axis-aligned artefacts
+    Simplex,
+}
+
+impl NoiseKind {
+    /// Noise between -1 and 1 at a point
+    pub fn sample(&self, seed: u64, p: Vec2) -> f32 {
+        match self {
+            NoiseKind::Value => value(seed, p),
+            NoiseKind::Perlin => perlin(seed, p),
+            NoiseKind::Simplex => simplex(seed, p),
+        }
+    }
+}
+
+fn value(seed: u64, p: Vec2) -> f32 {
+    let cell = p.floor();
+    let (x, y) = (cell.x as i32, cell.y as i32);
+    let t = p - cell;
+    let (u, v) = (fade(t.x), fade(t.y));
+    let corner = |dx, dy| unit(hash(seed, x + dx, y + dy));
+    let bottom = corner(0, 0) + u * (corner(1, 0) - corner(0, 0));
+    let top = corner(0, 1) + u * (corner(1, 1) - corner(0, 1));
+    bottom + v * (top - bottom)
+}
+
+fn perlin(seed: u64, p: Vec2) -> f32 {
+    let cell = p.floor();
+    let (x, y) = (cell.x as i32, cell.y as i32);
+    let t = p - cell;
+    let (u, v) = (fade(t.x), fade(t.y));
+    let corner = |dx: i32, dy: i32| {
+        let offset = t - Vec2::new(dx as f32, dy as f32);
+        gradient(hash(seed, x + dx, y + dy), offset)
+    };
+    let bottom = corner(0, 0) + u * (corner(1, 0) - corner(0, 0));
+    let top = corner(0, 1) + u * (corner(1, 1) - corner(0, 1));
+    // gradients of unit length reach √½ at most
+    ((bottom + v * (top - bottom)) * std::f32::consts::SQRT_2).clamp(-1.0, 1.0)
+}
+
+fn simplex(seed: u64, p: Vec2) -> f32 {
+    const F2: f32 = 0.36602542; // (√3 - 1) / 2
+    const G2: f32 = 0.21132487; // (3 - √3) / 6
+    let skew = (p.x + p.y) * F2;
+    let (i, j) = ((p.x + skew).floor(), (p.y + skew).floor());
+    let unskew = (i + j) * G2;
+    let d0 = p - Vec2::new(i - unskew, j - unskew);
+    let (i1, j1) = if d0.x > d0.y { (1, 0) } else { (0, 1) };
+    let d1 = d0 - Vec2::new(i1 as f32, j1 as f32) + Vec2::splat(G2);
+    let d2 = d0 - Vec2::ONE + Vec2::splat(2.0 * G2);
+    let (i, j) = (i as i32, j as i32);
+    let total: f32 = [(d0, 0, 0), (d1, i1, j1), (d2, 1, 1)]
+        .into_iter()
+        .map(|(d, di, dj)| {
+            let t = 0.5 - d.length_squared();
+            if t <= 0.0 {
+                return 0.0;
+            }
+            t.powi(4) * gradient(hash(seed, i + di, j + dj), d)
+        })
+        .sum();
+    (70.0 * total).clamp(-1.0, 1.0)
+}
+
+/// Octaves of noise added up, each finer and fainter than the one before
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoiseSettings {
+    pub kind: NoiseKind,
+    /// Features per world unit of the first octave
+    pub frequency: f32,
+    pub octaves: u32,
+    /// Frequency of each octave over that of the one before
+    pub lacunarity: f32,
+    /// Amplitude of each octave over that of the one before
+    pub gain: f32,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        NoiseSettings {
+            kind: NoiseKind::Perlin,
+            frequency: 0.1,
+            octaves: 4,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+impl NoiseSettings {
+    /// Noise between 0 and 1 at a point of the grid plane
+    pub fn sample(&self, seed: u64, p: Vec2) -> f32 {
+        let (mut total, mut norm) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (self.frequency, 1.0);
+        for octave in 0..self.octaves.max(1) {
+            let seed = mix(seed.wrapping_add(octave as u64));
+            total += amplitude * self.kind.sample(seed, p * frequency);
+            norm += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        0.5 + 0.5 * total / norm
+    }
+}
+
+/// Shape given to the land by lowering the elevation of some cells
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LandMask {
+    #[default]
+    None,
+    /// Land in the middle of the map, sinking towards its edges. Higher
+    /// `falloff`s keep more of the land at full height.
+    Island { falloff: f32 },
+    /// Land masses drawn by a coarser noise, whose lows sink into the sea
+    Continents { frequency: f32 },
+}
+
+/// Terrain given to cells up to some elevation and moisture, both from 0 to 1
+#[derive(Clone, Debug, PartialEq)]
+pub struct Biome {
+    pub terrain: String,
+    pub max_elevation: f32,
+    pub max_moisture: f32,
+}
+
+impl Biome {
+    pub fn new(terrain: impl Into<String>, max_elevation: f32, max_moisture: f32) -> Self {
+        Biome {
+            terrain: terrain.into(),
+            max_elevation,
+            max_moisture,
+        }
+    }
+}
+
+/// Fills maps of any primitive and shape with elevation, moisture and
+/// biomes out of noise sampled at the centres of their cells. The same seed
+/// and settings always give the same map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TerrainGenerator {
+    pub seed: u64,
+    pub elevation: NoiseSettings,
+    pub moisture: NoiseSettings,
+    pub mask: LandMask,
+    /// Biomes in the order they are tried, the first to fit a cell giving it
+    /// its terrain. Cells fitting none are left without.
+    pub biomes: Vec<Biome>,
+}
+
+impl TerrainGenerator {
+    /// Generator of islands in the sea of water, sand, grass, forest, hills
+    /// and mountain terrains
+    pub fn new(seed: u64) -> Self {
+        TerrainGenerator {
+            seed,
+            elevation: NoiseSettings::default(),
+            moisture: NoiseSettings {
+                frequency: 0.05,
+                octaves: 2,
+                ..default()
+            },
+            mask: LandMask::Island { falloff: 2.0 },
+            biomes: vec![
+                Biome::new("water", 0.35, 1.0),
+                Biome::new("sand", 0.4, 1.0),
+                Biome::new("grass", 0.65, 0.5),
+                Biome::new("forest", 0.65, 1.0),
+                Biome::new("hills", 0.8, 1.0),
+                Biome::new("mountain", 1.0, 1.0),
+            ],
+        }
+    }
+
+    /// Elevation, moisture and biome of every cell of a `width` by `height` map
+    pub fn generate<T: GridLayout>(
+        &self,
+        grid: &T,
+        width: u32,
+        height: u32,
+    ) -> GeneratedMap<T::Coord> {
+        let alignment = grid.alignment();
+        let cells: Vec<(T::Coord, Vec2)> = grid
+            .cells(width, height)
+            .into_iter()
+            .map(|cell| (cell, alignment.to_plane(grid.cell_pos(&cell))))
+            .collect();
+        let centre = cells.iter().map(|(_, p)| *p).sum::<Vec2>() / cells.len().max(1) as f32;
+        let radius = cells
+            .iter()
+            .map(|(_, p)| p.distance(centre))
+            .fold(f32::EPSILON, f32::max);
+        let (elevation_seed, moisture_seed, mask_seed) =
+            (mix(self.seed), mix(self.seed ^ 1), mix(self.seed ^ 2));
+
+        let mut map = GeneratedMap {
+            elevation: GridLayer::new(),
+            moisture: GridLayer::new(),
+            biomes: GridLayer::new(),
+        };
+        for (cell, p) in cells {
+            let mut elevation = self.elevation.sample(elevation_seed, p);
+            elevation *= match self.mask {
+                LandMask::None => 1.0,
+                LandMask::Island { falloff } => {
+                    1.0 - (p.distance(centre) / radius).powf(falloff.max(f32::EPSILON))
+                }
+                LandMask::Continents { frequency } => {
+                    let continents = NoiseSettings {
+                        frequency,
+                        octaves: 2,
+                        ..self.elevation
+                    };
+                    (2.0 * continents.sample(mask_seed, p) - 0.5).clamp(0.0, 1.0)
+                }
+            };
+            let moisture = self.moisture.sample(moisture_seed, p);
+            let biome = self
+                .biomes
+                .iter()
+                .find(|b| elevation <= b.max_elevation && moisture <= b.max_moisture);
+            if let Some(biome) = biome {
+                map.biomes.insert(cell, biome.terrain.clone());
+            }
+            map.elevation.insert(cell, elevation);
+            map.moisture.insert(cell, moisture);
+        }
+        map
+    }
+}
+
+/// Layers made by a `TerrainGenerator`
+#[derive(Clone)]
+pub struct GeneratedMap<K: Coords> {
+    pub elevation: GridLayer<K, f32>,
+    pub moisture: GridLayer<K, f32>,
+    /// Terrain names, as in the layers of a `LevelMap`
+    pub biomes: GridLayer<K, String>,
+}
+
+impl<K: Coords + Copy + Eq + Hash> GeneratedMap<K> {
+    /// Terrain layer of the biomes, to stand as the terrain of a grid
+    pub fn terrain(
+        &self,
+        registry: &TerrainRegistry,
+    ) -> Result<GridLayer<K, TerrainId>, UnknownTerrain> {
+        registry.layer(&self.biomes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::{HexCoord, HexOrientation, TriangleNeighbours},
+        primitives::{GridAlign, GridWrap, Hexes, Triangles},
+        terrain::Terrain,
+    };
+
+    #[test]
+    fn noise_is_smooth_and_bounded() {
+        for kind in [NoiseKind::Value, NoiseKind::Perlin, NoiseKind::Simplex] {
+            let samples: Vec<f32> = (0..2000)
+                .map(|i| kind.sample(7, Vec2::new(i as f32 * 0.01, 3.3)))
+                .collect();
+            assert!(samples.iter().all(|n| (-1.0..=1.0).contains(n)));
+            assert!(samples.windows(2).all(|w| (w[0] - w[1]).abs() < 0.1));
+            // not flat either
+            let (min, max) = samples
+                .iter()
+                .fold((1f32, -1f32), |(lo, hi), n| (lo.min(*n), hi.max(*n)));
+            assert!(max - min > 0.5, "{kind:?} ranges over {min}..{max}");
+            let other: Vec<f32> = (0..2000)
+                .map(|i| kind.sample(8, Vec2::new(i as f32 * 0.01, 3.3)))
+                .collect();
+            assert_ne!(samples, other);
+        }
+    }
+
+    #[test]
+    fn seeds_reproduce_worlds() {
+        let hexes = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::FlatUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let generator = TerrainGenerator::new(42);
+        let biomes = |generator: &TerrainGenerator| {
+            let map = generator.generate(&hexes, 24, 16);
+            let mut cells: Vec<(i32, i32, String)> = map
+                .biomes
+                .iter()
+                .map(|(c, b)| (c.q, c.r, b.clone()))
+                .collect();
+            cells.sort();
+            cells
+        };
+        let world = biomes(&generator);
+        assert_eq!(world.len(), 24 * 16);
+        assert_eq!(world, biomes(&generator.clone()));
+        assert_ne!(world, biomes(&TerrainGenerator::new(43)));
+
+        // the island is surrounded by sea
+        let map = generator.generate(&hexes, 24, 16);
+        for corner in [(0, 0), (23, 0), (0, 15), (23, 15)] {
+            let cell = hexes.at_offset(corner.0, corner.1);
+            assert_eq!(map.biomes.get(&cell).map(String::as_str), Some("water"));
+        }
+        let registry: TerrainRegistry = ["water", "sand", "grass", "forest", "hills", "mountain"]
+            .into_iter()
+            .map(|name| Terrain::new(name, Color::WHITE))
+            .collect();
+        let terrain = map.terrain(&registry).unwrap();
+        assert_eq!(terrain.get(&HexCoord::ZERO), registry.id("water").as_ref());
+    }
+
+    #[test]
+    fn continents_on_triangles() {
+        let triangles = Triangles {
+            size: 1.0,
+            alignment: GridAlign::XY,
+            neighbors: TriangleNeighbours::Strict,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let generator = TerrainGenerator {
+            mask: LandMask::Continents { frequency: 0.02 },
+            biomes: vec![Biome::new("sea", 0.3, 1.0), Biome::new("land", 1.0, 1.0)],
+            ..TerrainGenerator::new(7)
+        };
+        let map = generator.generate(&triangles, 40, 40);
+        assert_eq!(map.biomes.len(), 2 * 40 * 40);
+        let sea = map.biomes.iter().filter(|(_, b)| *b == "sea").count();
+        assert!(sea > 0 && sea < map.biomes.len());
+        assert!(map.elevation.iter().all(|(_, e)| (0.0..=1.0).contains(e)));
+    }
+}
@@ -0,0 +1,602 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use std::{collections::BinaryHeap, hash::Hash, marker::PhantomData};
+
+use super::{
+    coordinates::Coords,
+    pathfinding::{Path, Step},
+    terrain::{PathCosts, SeenCells},
+    topology::Topology,
+    GridConfig, GridLayout,
+};
+
+/// Column and row of a cluster of cells
+pub type Chunk = (i32, i32);
+
+/// Border runs of at least this many cell pairs get an entrance at each end
+/// besides the one in the middle
+const LONG_BORDER: usize = 6;
+
+/// Cells reached from `from` without leaving the cells `within`, with their
+/// cost and the cell they are entered from
+fn search<K, T>(
+    grid: &T,
+    from: K,
+    within: impl Fn(&K) -> bool,
+    cost: impl Fn(&K) -> Option<u32>,
+) -> HashMap<K, (u32, K)>
+where
+    K: Coords + Copy + Eq + Hash,
+    T: Topology<K>,
+{
+    let mut tree = HashMap::new();
+    tree.insert(from, (0, from));
+    let mut open = BinaryHeap::from([Step {
+        estimate: 0,
+        cell: from,
+    }]);
+    while let Some(Step { estimate, cell }) = open.pop() {
+        if estimate > tree[&cell].0 {
+            continue;
+        }
+        for n in grid.neighbours(&cell) {
+            if !within(&n) {
+                continue;
+            }
+            let Some(step) = cost(&n) else {
+                continue;
+            };
+            let total = estimate + step;
+            if tree.get(&n).is_none_or(|(spent, _)| total < *spent) {
+                tree.insert(n, (total, cell));
+                open.push(Step {
+                    estimate: total,
+                    cell: n,
+                });
+            }
+        }
+    }
+    tree
+}
+
+/// Path from the root of a `search` tree to `to`
+fn trace<K: Coords + Copy + Eq + Hash>(tree: &HashMap<K, (u32, K)>, to: K) -> Path<K> {
+    let mut cells = vec![to];
+    let mut cell = to;
+    while let Some((_, previous)) = tree.get(&cell).filter(|(_, p)| *p != cell) {
+        cells.push(*previous);
+        cell = *previous;
+    }
+    cells.reverse();
+    Path {
+        cells,
+        cost: tree[&to].0,
+    }
+}
+
+/// Clusters of cells with the cheapest paths between their entrances worked
+/// out beforehand, for long paths to be found over entrances rather than
+/// cells (HPA*). Paths found are close to the cheapest, not always the
+/// cheapest. Clusters are `chunk` columns by `chunk` rows of the map; those
+/// around cells whose cost changed are worked out again by `update` and
+/// `update_cells`.
+#[derive(Resource, Clone)]
+pub struct PathHierarchy<K: Coords> {
+    pub chunk: u32,
+    /// Cost of entering each cell, as last read
+    costs: HashMap<K, Option<u32>>,
+    chunks: HashMap<K, Chunk>,
+    clusters: HashMap<Chunk, Vec<K>>,
+    /// Cell pairs across the border of two clusters units go through, the
+    /// first of each pair in the lesser of the two clusters
+    borders: HashMap<(Chunk, Chunk), Vec<(K, K)>>,
+    /// Cells across a border each entrance leads to
+    crossings: HashMap<K, Vec<K>>,
+    /// Cheapest paths from each entrance to the others of its cluster
+    paths: HashMap<K, Vec<Path<K>>>,
+}
+
+impl<K: Coords + Copy + Eq + Hash> PathHierarchy<K> {
+    pub fn new(chunk: u32) -> Self {
+        PathHierarchy {
+            chunk: chunk.max(1),
+            costs: HashMap::new(),
+            chunks: HashMap::new(),
+            clusters: HashMap::new(),
+            borders: HashMap::new(),
+            crossings: HashMap::new(),
+            paths: HashMap::new(),
+        }
+    }
+
+    pub fn chunk_of<T: Topology<K>>(&self, grid: &T, coord: &K) -> Chunk {
+        let (col, row) = grid.offset(coord);
+        let size = self.chunk as i32;
+        (col.div_euclid(size), row.div_euclid(size))
+    }
+
+    pub fn cost(&self, coord: &K) -> Option<u32> {
+        self.costs.get(coord).copied().flatten()
+    }
+
+    /// Cells leading out of their cluster
+    pub fn entrances(&self) -> impl Iterator<Item = &K> {
+        self.crossings.keys()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.costs.is_empty()
+    }
+
+    /// Takes in the `cells` not known yet and reads the cost of every cell
+    /// again, `cost` being as for `find_path`. Only the clusters around cells
+    /// whose cost changed are worked out again; returns how many.
+    pub fn update<T, F>(&mut self, grid: &T, cells: impl IntoIterator<Item = K>, cost: F) -> usize
+    where
+        T: Topology<K>,
+        F: Fn(&K) -> Option<u32>,
+    {
+        let known: Vec<K> = self.costs.keys().copied().collect();
+        self.update_cells(grid, cells.into_iter().chain(known), cost)
+    }
+
+    /// Reads the cost of the `changed` cells alone again, taking in those not
+    /// known yet, and works out again the clusters around those whose cost
+    /// changed; returns how many.
+    pub fn update_cells<T, F>(
+        &mut self,
+        grid: &T,
+        changed: impl IntoIterator<Item = K>,
+        cost: F,
+    ) -> usize
+    where
+        T: Topology<K>,
+        F: Fn(&K) -> Option<u32>,
+    {
+        let mut dirty: HashSet<Chunk> = HashSet::new();
+        for cell in changed {
+            let cell = grid.normalize(&cell);
+            let new = cost(&cell);
+            match self.costs.insert(cell, new) {
+                Some(old) if old == new => {}
+                Some(_) => {
+                    dirty.insert(self.chunks[&cell]);
+                }
+                None => {
+                    let chunk = self.chunk_of(grid, &cell);
+                    self.chunks.insert(cell, chunk);
+                    self.clusters.entry(chunk).or_default().push(cell);
+                    dirty.insert(chunk);
+                }
+            }
+        }
+        if dirty.is_empty() {
+            return 0;
+        }
+        self.rebuild(grid, dirty)
+    }
+
+    /// Finds the borders of the `dirty` clusters again, then the paths
+    /// between the entrances of every cluster whose entrances moved; returns
+    /// how many clusters were touched
+    fn rebuild<T: Topology<K>>(&mut self, grid: &T, dirty: HashSet<Chunk>) -> usize {
+        // borders of the dirty clusters, found again
+        let mut touched = dirty.clone();
+        let stale: Vec<(Chunk, Chunk)> = self
+            .borders
+            .keys()
+            .filter(|(a, b)| dirty.contains(a) || dirty.contains(b))
+            .copied()
+            .collect();
+        for key in stale {
+            for (a, b) in self.borders.remove(&key).unwrap() {
+                self.cross_off(&a, &b);
+                self.cross_off(&b, &a);
+            }
+            touched.extend([key.0, key.1]);
+        }
+        let mut pairs: HashMap<(Chunk, Chunk), Vec<(K, K)>> = HashMap::new();
+        for chunk in &dirty {
+            for cell in &self.clusters[chunk] {
+                if self.cost(cell).is_none() {
+                    continue;
+                }
+                for n in grid.neighbours(cell) {
+                    let Some(other) = self.chunks.get(&n) else {
+                        continue;
+                    };
+                    // pairs between two dirty clusters are found from the lesser
+                    if other == chunk
+                        || self.cost(&n).is_none()
+                        || (dirty.contains(other) && other < chunk)
+                    {
+                        continue;
+                    }
+                    let (key, pair) = if chunk < other {
+                        ((*chunk, *other), (*cell, n))
+                    } else {
+                        ((*other, *chunk), (n, *cell))
+                    };
+                    pairs.entry(key).or_default().push(pair);
+                }
+            }
+        }
+        for (key, pairs) in pairs {
+            let transitions = entrances(grid, pairs);
+            for (a, b) in &transitions {
+                self.crossings.entry(*a).or_default().push(*b);
+                self.crossings.entry(*b).or_default().push(*a);
+            }
+            self.borders.insert(key, transitions);
+            touched.extend([key.0, key.1]);
+        }
+
+        // paths between the entrances of every cluster whose entrances moved
+        for chunk in &touched {
+            let Some(cells) = self.clusters.get(chunk).cloned() else {
+                continue;
+            };
+            for cell in &cells {
+                self.paths.remove(cell);
+            }
+            let entrances: Vec<K> = cells
+                .iter()
+                .filter(|c| self.crossings.contains_key(*c))
+                .copied()
+                .collect();
+            for from in &entrances {
+                let tree = search(
+                    grid,
+                    *from,
+                    |c| self.chunks.get(c) == Some(chunk),
+                    |c| self.cost(c),
+                );
+                let paths = entrances
+                    .iter()
+                    .filter(|to| *to != from && tree.contains_key(*to))
+                    .map(|to| trace(&tree, *to))
+                    .collect();
+                self.paths.insert(*from, paths);
+            }
+        }
+        touched.len()
+    }
+
+    fn cross_off(&mut self, from: &K, to: &K) {
+        if let Some(across) = self.crossings.get_mut(from) {
+            if let Some(i) = across.iter().position(|c| c == to) {
+                across.swap_remove(i);
+            }
+            if across.is_empty() {
+                self.crossings.remove(from);
+            }
+        }
+    }
+
+    /// Path from `start` to `goal` over the entrances of the clusters, at
+    /// the costs last read by `update`
+    pub fn find_path<T: Topology<K>>(&self, grid: &T, start: K, goal: K) -> Option<Path<K>> {
+        let (start, goal) = (grid.normalize(&start), grid.normalize(&goal));
+        let (from, to) = (*self.chunks.get(&start)?, *self.chunks.get(&goal)?);
+        self.cost(&goal)?;
+        let cost = |c: &K| self.cost(c);
+        let within = |chunk: Chunk| move |c: &K| self.chunks.get(c) == Some(&chunk);
+
+        // ways out of the cluster of the start, and into the goal
+        let out = search(grid, start, within(from), cost);
+        let mut into: HashMap<K, Path<K>> = HashMap::new();
+        for entrance in self.clusters[&to].iter() {
+            if !self.crossings.contains_key(entrance) {
+                continue;
+            }
+            let tree = search(grid, *entrance, within(to), cost);
+            if tree.contains_key(&goal) {
+                into.insert(*entrance, trace(&tree, goal));
+            }
+        }
+
+        // A* over the entrances, each hop walking the cells after the one it
+        // leaves from
+        let mut spent: HashMap<K, u32> = HashMap::new();
+        let mut came: HashMap<K, (K, Vec<K>)> = HashMap::new();
+        let mut closed: HashSet<K> = HashSet::new();
+        let mut open = BinaryHeap::new();
+        spent.insert(start, 0);
+        open.push(Step {
+            estimate: grid.distance(&start, &goal),
+            cell: start,
+        });
+        while let Some(Step { cell, .. }) = open.pop() {
+            if cell == goal {
+                let cost = spent[&goal];
+                let mut segments = vec![];
+                let mut at = goal;
+                while let Some((previous, cells)) = came.get(&at) {
+                    segments.push(cells);
+                    at = *previous;
+                }
+                let mut cells = vec![start];
+                cells.extend(segments.into_iter().rev().flatten());
+                return Some(Path { cells, cost });
+            }
+            if !closed.insert(cell) {
+                continue;
+            }
+            let mut hops: Vec<(K, u32, Vec<K>)> = Vec::new();
+            if cell == start {
+                for (to, _) in out.iter() {
+                    if *to != start && (*to == goal || self.crossings.contains_key(to)) {
+                        let path = trace(&out, *to);
+                        hops.push((*to, path.cost, path.cells[1..].to_vec()));
+                    }
+                }
+            }
+            for path in self.paths.get(&cell).into_iter().flatten() {
+                hops.push((
+                    path.cells[path.cells.len() - 1],
+                    path.cost,
+                    path.cells[1..].to_vec(),
+                ));
+            }
+            for across in self.crossings.get(&cell).into_iter().flatten() {
+                if let Some(step) = cost(across) {
+                    hops.push((*across, step, vec![*across]));
+                }
+            }
+            if let Some(path) = into.get(&cell) {
+                hops.push((goal, path.cost, path.cells[1..].to_vec()));
+            }
+            let here = spent[&cell];
+            for (next, step, cells) in hops {
+                let total = here + step;
+                if spent.get(&next).is_none_or(|s| total < *s) {
+                    spent.insert(next, total);
+                    came.insert(next, (cell, cells));
+                    open.push(Step {
+                        estimate: total + grid.distance(&next, &goal),
+                        cell: next,
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Cell pairs to go through a border by, out of all those across it: the
+/// middle of each unbroken run of pairs, and its ends if it is long
+fn entrances<K, T>(grid: &T, mut pairs: Vec<(K, K)>) -> Vec<(K, K)>
+where
+    K: Coords + Copy + Eq + Hash,
+    T: Topology<K>,
+{
+    let side = |a: &K, b: &K| a == b || grid.neighbours(a).contains(b);
+    let linked = |p: &(K, K), q: &(K, K)| side(&p.0, &q.0) && side(&p.1, &q.1);
+    let mut picked = Vec::new();
+    while let Some(first) = pairs.pop() {
+        let mut run = vec![first];
+        let mut i = 0;
+        while i < run.len() {
+            let (linked_to, rest): (Vec<_>, Vec<_>) =
+                pairs.into_iter().partition(|p| linked(&run[i], p));
+            pairs = rest;
+            run.extend(linked_to);
+            i += 1;
+        }
+        // walked again from an end for the middle to be halfway along
+        let end = *run
+            .iter()
+            .min_by_key(|p| run.iter().filter(|q| linked(p, q)).count())
+            .unwrap();
+        let mut left: Vec<(K, K)> = run.into_iter().filter(|p| *p != end).collect();
+        let mut ordered = vec![end];
+        let mut i = 0;
+        while i < ordered.len() {
+            let (linked_to, rest): (Vec<_>, Vec<_>) =
+                left.into_iter().partition(|p| linked(&ordered[i], p));
+            left = rest;
+            ordered.extend(linked_to);
+            i += 1;
+        }
+        picked.push(ordered[ordered.len() / 2]);
+        if ordered.len() >= LONG_BORDER {
+            picked.push(ordered[0]);
+            picked.push(ordered[ordered.len() - 1]);
+        }
+    }
+    picked
+}
+
+/// `PathHierarchy` of the cells of the grid of `T`, kept up with their costs
+pub struct PathHierarchyPlugin<T> {
+    chunk: u32,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> PathHierarchyPlugin<T> {
+    /// Clusters of `chunk` by `chunk` cells
+    pub fn new(chunk: u32) -> Self {
+        PathHierarchyPlugin {
+            chunk,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: GridLayout> Plugin for PathHierarchyPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(PathHierarchy::<T::Coord>::new(self.chunk))
+            .add_system(update_hierarchy::<T>.run_if(resource_exists::<GridConfig<T>>()));
+    }
+}
+
+/// Works out again the clusters around cells added to or taken off the map
+/// or whose terrain changed, reading the cost of those cells alone
+pub fn update_hierarchy<T: GridLayout>(
+    grid: Res<GridConfig<T>>,
+    costs: PathCosts<T::Coord>,
+    mut hierarchy: ResMut<PathHierarchy<T::Coord>>,
+    mut seen: Local<SeenCells<T::Coord>>,
+) {
+    if !costs.is_changed() {
+        return;
+    }
+    let changed = costs.changed_cells(&mut seen);
+    hierarchy.update_cells(&grid.0, changed, |c| costs.cost(c));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::{HexCoord, HexOrientation, SquareCoord},
+        layers::GridLayer,
+        pathfinding::find_path,
+        primitives::{GridAlign, GridWrap, Hexes, Squares},
+        terrain::{Terrain, TerrainId, TerrainPlugin, TerrainRegistry},
+        testing::spawn_map,
+        GridMap, GridPlugin,
+    };
+
+    /// Cells of the path follow each other and can all be entered
+    fn walkable<K, T>(grid: &T, path: &Path<K>, cost: impl Fn(&K) -> Option<u32>) -> bool
+    where
+        K: Coords + Copy + Eq + Hash,
+        T: Topology<K>,
+    {
+        let steps = path.cells.windows(2);
+        let entered: Option<u32> = path.cells[1..].iter().map(&cost).sum();
+        steps
+            .into_iter()
+            .all(|w| grid.neighbours(&w[0]).contains(&w[1]))
+            && entered == Some(path.cost)
+    }
+
+    #[test]
+    fn square_paths_come_close_to_the_cheapest() {
+        let squares = Squares {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let square = |q, r| SquareCoord { q, r };
+        let in_map = |c: &SquareCoord| (0..40).contains(&c.q) && (0..40).contains(&c.r);
+        // two walls with gaps at opposite ends, and mud
+        let wall = |c: &SquareCoord| (c.q == 13 && c.r > 2) || (c.q == 27 && c.r < 37);
+        let mud = |c: &SquareCoord| (5..10).contains(&c.q);
+        let cost = |c: &SquareCoord| (in_map(c) && !wall(c)).then_some(if mud(c) { 3 } else { 1 });
+        let mut hierarchy = PathHierarchy::new(8);
+        let cells = squares.cells(40, 40);
+        assert_eq!(hierarchy.update(&squares, cells.clone(), cost), 25);
+        assert_eq!(hierarchy.update(&squares, cells, cost), 0);
+
+        for (start, goal) in [
+            (square(0, 39), square(39, 0)),
+            (square(2, 2), square(6, 5)),
+            (square(20, 20), square(20, 21)),
+            (square(39, 39), square(0, 0)),
+        ] {
+            let path = hierarchy.find_path(&squares, start, goal).unwrap();
+            let best = find_path(&squares, start, goal, cost).unwrap();
+            assert_eq!(
+                (path.cells[0], path.cells[path.cells.len() - 1]),
+                (start, goal)
+            );
+            assert!(walkable(&squares, &path, cost));
+            assert!(
+                path.cost as f32 <= best.cost as f32 * 1.2,
+                "{} over {}",
+                path.cost,
+                best.cost
+            );
+        }
+        assert!(hierarchy
+            .find_path(&squares, square(0, 0), square(13, 20))
+            .is_none());
+        assert!(hierarchy
+            .find_path(&squares, square(0, 0), square(50, 0))
+            .is_none());
+
+        // the gap of the first wall closed, read through its cells alone
+        let gap = [square(13, 0), square(13, 1), square(13, 2)];
+        let closed = |c: &SquareCoord| if gap.contains(c) { None } else { cost(c) };
+        let touched = hierarchy.update_cells(&squares, gap, closed);
+        assert!((1..25).contains(&touched), "{touched}");
+        assert!(hierarchy
+            .find_path(&squares, square(0, 39), square(39, 0))
+            .is_none());
+        assert_eq!(hierarchy.update_cells(&squares, gap, closed), 0);
+    }
+
+    #[test]
+    fn hex_clusters_follow_the_terrain() {
+        let hexes = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::PointyUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let terrains = vec![
+            Terrain::new("grass", Color::GREEN),
+            Terrain {
+                passable: false,
+                ..Terrain::new("lake", Color::BLUE)
+            },
+        ];
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(GridPlugin::new(hexes.clone()))
+            .add_plugin(TerrainPlugin::<Hexes>::new(terrains))
+            .add_plugin(PathHierarchyPlugin::<Hexes>::new(6))
+            .add_startup_system(spawn_map::<Hexes>(24, 24));
+        app.update();
+        let (start, goal) = (hexes.at_offset(0, 12), hexes.at_offset(23, 12));
+        let hierarchy = app.world.resource::<PathHierarchy<HexCoord>>();
+        let path = hierarchy.find_path(&hexes, start, goal).unwrap();
+        assert!(path.cost <= 26, "{}", path.cost);
+
+        // a lake across the middle, but for the top rows
+        let lake = app.world.resource::<TerrainRegistry>().id("lake").unwrap();
+        let shore: Vec<HexCoord> = (3..24).map(|row| hexes.at_offset(12, row)).collect();
+        let mut layer = app.world.resource_mut::<GridLayer<HexCoord, TerrainId>>();
+        for cell in &shore {
+            layer.insert(*cell, lake);
+        }
+        let before = app.world.resource::<PathHierarchy<HexCoord>>().clone();
+        app.update();
+        let hierarchy = app.world.resource::<PathHierarchy<HexCoord>>();
+        // only the clusters along the lake were worked out again
+        let moved = hierarchy
+            .paths
+            .iter()
+            .filter(|(from, paths)| before.paths.get(*from) != Some(*paths))
+            .map(|(from, _)| hierarchy.chunk_of(&hexes, from))
+            .collect::<HashSet<_>>();
+        assert!(!moved.is_empty());
+        assert!(moved.iter().all(|(col, _)| (1..=2).contains(col)));
+
+        let path = hierarchy.find_path(&hexes, start, goal).unwrap();
+        let cost = |c: &HexCoord| hierarchy.cost(c);
+        let best = find_path(&hexes, start, goal, cost).unwrap();
+        assert!(walkable(&hexes, &path, cost));
+        assert!(!path.cells.iter().any(|c| shore.contains(c)));
+        assert!(
+            path.cost as f32 <= best.cost as f32 * 1.2,
+            "{} over {}",
+            path.cost,
+            best.cost
+        );
+
+        // a cell taken off the map can no longer be entered
+        let cell = app.world.resource_mut::<GridMap<HexCoord>>().remove(&start);
+        app.world.despawn(cell.unwrap());
+        app.update();
+        let hierarchy = app.world.resource::<PathHierarchy<HexCoord>>();
+        assert_eq!(hierarchy.cost(&start), None);
+        assert!(hierarchy.find_path(&hexes, goal, start).is_none());
+    }
+}
@@ -0,0 +1,519 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use std::{collections::BinaryHeap, hash::Hash, marker::PhantomData};
+
+use super::{
+    coordinates::Coords,
+    fog::Faction,
+    layers::GridLayer,
+    pathfinding::{reachable, Step},
+    topology::Topology,
+    GridConfig, GridLayout, GridPosition,
+};
+
+/// Cheapest cost of reaching every cell from the nearest of many sources,
+/// e.g. how far the closest enemy is. Sources come and go without searching
+/// the whole map again. Costs are as for `find_path`, the same ones to be
+/// given to every call until `rebuild`.
+#[derive(Clone)]
+pub struct DistanceField<K: Coords> {
+    /// Cells farther than this from every source are left out
+    pub limit: u32,
+    /// Number of sources on each cell
+    sources: HashMap<K, usize>,
+    distances: GridLayer<K, u32>,
+    nearest: HashMap<K, K>,
+}
+
+impl<K: Coords + Copy + Eq + Hash> DistanceField<K> {
+    pub fn new(limit: u32) -> Self {
+        DistanceField {
+            limit,
+            sources: HashMap::new(),
+            distances: GridLayer::new(),
+            nearest: HashMap::new(),
+        }
+    }
+
+    pub fn distance(&self, coord: &K) -> Option<u32> {
+        self.distances.get(coord).copied()
+    }
+
+    /// Source the cell is the closest to
+    pub fn nearest(&self, coord: &K) -> Option<K> {
+        self.nearest.get(coord).copied()
+    }
+
+    pub fn layer(&self) -> &GridLayer<K, u32> {
+        &self.distances
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = &K> {
+        self.sources.keys()
+    }
+
+    pub fn add_source<T, F>(&mut self, grid: &T, source: K, cost: F)
+    where
+        T: Topology<K>,
+        F: Fn(&K) -> Option<u32>,
+    {
+        let source = grid.normalize(&source);
+        let count = self.sources.entry(source).or_default();
+        *count += 1;
+        if *count > 1 || self.distance(&source) == Some(0) {
+            return;
+        }
+        self.distances.insert(source, 0);
+        self.nearest.insert(source, source);
+        let open = BinaryHeap::from([Step {
+            estimate: 0,
+            cell: source,
+        }]);
+        self.spread(grid, open, cost);
+    }
+
+    /// Takes one source off the cell, the cells it was the nearest source of
+    /// measured again from the others
+    pub fn remove_source<T, F>(&mut self, grid: &T, source: K, cost: F)
+    where
+        T: Topology<K>,
+        F: Fn(&K) -> Option<u32>,
+    {
+        let source = grid.normalize(&source);
+        let Some(count) = self.sources.get_mut(&source) else {
+            return;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        self.sources.remove(&source);
+        let region: Vec<K> = self
+            .nearest
+            .iter()
+            .filter(|(_, s)| **s == source)
+            .map(|(c, _)| *c)
+            .collect();
+        for cell in &region {
+            self.distances.remove(cell);
+            self.nearest.remove(cell);
+        }
+        // spread again from the cells bordering the region
+        let mut open = BinaryHeap::new();
+        for cell in &region {
+            for n in grid.neighbours(cell) {
+                if let Some(estimate) = self.distance(&n) {
+                    open.push(Step { estimate, cell: n });
+                }
+            }
+        }
+        self.spread(grid, open, cost);
+    }
+
+    pub fn move_source<T, F>(&mut self, grid: &T, from: K, to: K, cost: F)
+    where
+        T: Topology<K>,
+        F: Fn(&K) -> Option<u32>,
+    {
+        self.remove_source(grid, from, &cost);
+        self.add_source(grid, to, &cost);
+    }
+
+    /// Measures every cell again, after costs changed
+    pub fn rebuild<T, F>(&mut self, grid: &T, cost: F)
+    where
+        T: Topology<K>,
+        F: Fn(&K) -> Option<u32>,
+    {
+        self.distances.clear();
+        self.nearest.clear();
+        let mut open = BinaryHeap::new();
+        for source in self.sources.keys() {
+            self.distances.insert(*source, 0);
+            self.nearest.insert(*source, *source);
+            open.push(Step {
+                estimate: 0,
+                cell: *source,
+            });
+        }
+        self.spread(grid, open, cost);
+    }
+
+    /// Dijkstra search out of the `open` cells, lowering the distance of the
+    /// cells it reaches for less
+    fn spread<T, F>(&mut self, grid: &T, mut open: BinaryHeap<Step<K>>, cost: F)
+    where
+        T: Topology<K>,
+        F: Fn(&K) -> Option<u32>,
+    {
+        while let Some(Step { estimate, cell }) = open.pop() {
+            if self.distance(&cell) != Some(estimate) {
+                continue;
+            }
+            let source = self.nearest[&cell];
+            for n in grid.neighbours(&cell) {
+                let Some(step) = cost(&n) else {
+                    continue;
+                };
+                let total = estimate + step;
+                if total <= self.limit && self.distance(&n).is_none_or(|d| total < d) {
+                    self.distances.insert(n, total);
+                    self.nearest.insert(n, source);
+                    open.push(Step {
+                        estimate: total,
+                        cell: n,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// How the influence of a unit fades with distance
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Falloff {
+    /// Full strength throughout the radius
+    Constant,
+    /// Down by equal steps to nothing just past the radius
+    #[default]
+    Linear,
+    /// Kept by this factor with every cell away
+    Exponential(f32),
+}
+
+impl Falloff {
+    /// Part of the strength left `distance` cells away
+    pub fn at(&self, distance: u32, radius: u32) -> f32 {
+        match self {
+            Falloff::Constant => 1.0,
+            Falloff::Linear => 1.0 - distance as f32 / (radius + 1) as f32,
+            Falloff::Exponential(factor) => factor.powi(distance as i32),
+        }
+    }
+}
+
+/// Weight a unit carries over the cells around it, e.g. the threat it poses
+/// or the hold it has on the land, counted for its `Faction`
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Influence {
+    pub strength: f32,
+    pub radius: u32,
+    pub falloff: Falloff,
+}
+
+/// Influence of many sources added up on every cell. Each source keeps what
+/// it gave, taken back off when it moves or goes.
+#[derive(Clone)]
+pub struct InfluenceMap<K: Coords> {
+    values: GridLayer<K, f32>,
+    given: HashMap<Entity, Vec<(K, f32)>>,
+}
+
+impl<K: Coords + Copy + Eq + Hash> Default for InfluenceMap<K> {
+    fn default() -> Self {
+        InfluenceMap {
+            values: GridLayer::new(),
+            given: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Coords + Copy + Eq + Hash> InfluenceMap<K> {
+    /// Sum of the influence on the cell, 0 out of reach of every source
+    pub fn get(&self, coord: &K) -> f32 {
+        self.values.get(coord).copied().unwrap_or(0.0)
+    }
+
+    pub fn layer(&self) -> &GridLayer<K, f32> {
+        &self.values
+    }
+
+    /// Spreads the influence of `source` from `coord`, in place of what it
+    /// spread before
+    pub fn set<T: Topology<K>>(
+        &mut self,
+        grid: &T,
+        source: Entity,
+        coord: K,
+        influence: &Influence,
+    ) {
+        self.remove(source);
+        let given: Vec<(K, f32)> = reachable(grid, coord, influence.radius, |_| Some(1))
+            .into_iter()
+            .map(|(cell, d)| {
+                let value = influence.strength * influence.falloff.at(d, influence.radius);
+                (cell, value)
+            })
+            .collect();
+        for (cell, value) in &given {
+            self.values.insert(*cell, self.get(cell) + value);
+        }
+        self.given.insert(source, given);
+    }
+
+    pub fn remove(&mut self, source: Entity) {
+        let Some(given) = self.given.remove(&source) else {
+            return;
+        };
+        for (cell, value) in given {
+            let Some(total) = self.values.get_mut(&cell) else {
+                continue;
+            };
+            *total -= value;
+            // what is left once every source is gone is rounding error
+            if total.abs() < 1e-4 {
+                self.values.remove(&cell);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.given.is_empty()
+    }
+}
+
+/// Influence of the units of every faction
+#[derive(Resource)]
+pub struct Influences<K: Coords> {
+    maps: HashMap<Faction, InfluenceMap<K>>,
+    factions: HashMap<Entity, Faction>,
+}
+
+impl<K: Coords + Copy + Eq + Hash> Default for Influences<K> {
+    fn default() -> Self {
+        Influences {
+            maps: HashMap::new(),
+            factions: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Coords + Copy + Eq + Hash> Influences<K> {
+    pub fn get(&self, faction: &Faction) -> Option<&InfluenceMap<K>> {
+        self.maps.get(faction)
+    }
+
+    pub fn influence(&self, faction: &Faction, coord: &K) -> f32 {
+        self.get(faction).map_or(0.0, |map| map.get(coord))
+    }
+
+    /// Influence of every other faction on the cell
+    pub fn threat(&self, faction: &Faction, coord: &K) -> f32 {
+        self.maps
+            .iter()
+            .filter(|(f, _)| *f != faction)
+            .map(|(_, map)| map.get(coord))
+            .sum()
+    }
+
+    /// Faction with the most influence on the cell, if any has some
+    pub fn control(&self, coord: &K) -> Option<Faction> {
+        self.maps
+            .iter()
+            .map(|(f, map)| (*f, map.get(coord)))
+            .filter(|(_, value)| *value > 0.0)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(f, _)| f)
+    }
+
+    pub fn set<T: Topology<K>>(
+        &mut self,
+        grid: &T,
+        source: Entity,
+        faction: Faction,
+        coord: K,
+        influence: &Influence,
+    ) {
+        if self.factions.get(&source).is_some_and(|f| *f != faction) {
+            self.remove(source);
+        }
+        self.factions.insert(source, faction);
+        self.maps
+            .entry(faction)
+            .or_default()
+            .set(grid, source, coord, influence);
+    }
+
+    pub fn remove(&mut self, source: Entity) {
+        let Some(faction) = self.factions.remove(&source) else {
+            return;
+        };
+        if let Some(map) = self.maps.get_mut(&faction) {
+            map.remove(source);
+            if map.is_empty() {
+                self.maps.remove(&faction);
+            }
+        }
+    }
+}
+
+/// `Influences` of the units standing on the grid of `T`
+pub struct InfluencePlugin<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for InfluencePlugin<T> {
+    fn default() -> Self {
+        InfluencePlugin(PhantomData)
+    }
+}
+
+impl<T: GridLayout> Plugin for InfluencePlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Influences::<T::Coord>::default())
+            .add_system(update_influence::<T>.run_if(resource_exists::<GridConfig<T>>()));
+    }
+}
+
+/// Sources whose influence may have changed
+type Spread<K> = Or<(
+    Changed<GridPosition<K>>,
+    Changed<Influence>,
+    Changed<Faction>,
+)>;
+
+/// Units with the influence they spread
+type Sources<'a, K> = (Entity, &'a GridPosition<K>, &'a Influence, &'a Faction);
+
+/// Spreads again the influence of the units that moved, changed or left
+pub fn update_influence<T: GridLayout>(
+    grid: Res<GridConfig<T>>,
+    mut influences: ResMut<Influences<T::Coord>>,
+    moved: Query<Sources<T::Coord>, Spread<T::Coord>>,
+    mut removed: RemovedComponents<Influence>,
+    mut off_grid: RemovedComponents<GridPosition<T::Coord>>,
+) {
+    let gone: HashSet<Entity> = removed.iter().chain(off_grid.iter()).collect();
+    for source in gone {
+        influences.remove(source);
+    }
+    for (source, position, influence, faction) in moved.iter() {
+        influences.set(&grid.0, source, *faction, position.0, influence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::{HexCoord, HexOrientation, SquareCoord},
+        primitives::{GridAlign, GridWrap, Hexes, Squares},
+        testing::square,
+        GridPlugin,
+    };
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn distance_fields_follow_their_sources() {
+        let grid = Squares {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let in_map = |c: &SquareCoord| (0..10).contains(&c.q) && (0..10).contains(&c.r);
+        let wall = |c: &SquareCoord| c.q == 5 && c.r > 0;
+        let cost = |c: &SquareCoord| (in_map(c) && !wall(c)).then_some(1);
+        // the same distances as the cheapest of one search from each source
+        let expected = |sources: &[SquareCoord]| {
+            let searches: Vec<_> = sources
+                .iter()
+                .map(|s| reachable(&grid, *s, 20, cost))
+                .collect();
+            let mut layer = GridLayer::new();
+            for search in &searches {
+                for (cell, d) in search {
+                    let best = layer.get(cell).map_or(*d, |b: &u32| (*b).min(*d));
+                    layer.insert(*cell, best);
+                }
+            }
+            layer
+        };
+        let same = |field: &DistanceField<SquareCoord>, layer: &GridLayer<SquareCoord, u32>| {
+            field.layer().len() == layer.len()
+                && layer.iter().all(|(c, d)| field.distance(c) == Some(*d))
+        };
+
+        let mut field = DistanceField::new(20);
+        let (a, b, c) = (square(0, 9), square(9, 9), square(9, 0));
+        field.add_source(&grid, a, cost);
+        field.add_source(&grid, b, cost);
+        assert!(same(&field, &expected(&[a, b])));
+        assert_eq!(field.distance(&square(4, 9)), Some(4));
+        // around the wall, by the gap at the bottom
+        assert_eq!(field.distance(&square(6, 9)), Some(3));
+        assert_eq!(field.nearest(&square(6, 0)), Some(b));
+
+        field.move_source(&grid, a, c, cost);
+        assert!(same(&field, &expected(&[b, c])));
+        assert_eq!(field.nearest(&square(6, 0)), Some(c));
+
+        // two sources on a cell, one left
+        field.add_source(&grid, b, cost);
+        field.remove_source(&grid, b, cost);
+        assert!(same(&field, &expected(&[b, c])));
+        field.remove_source(&grid, b, cost);
+        assert!(same(&field, &expected(&[c])));
+
+        // the wall opens
+        let open = |c: &SquareCoord| in_map(c).then_some(1);
+        field.rebuild(&grid, open);
+        assert_eq!(field.distance(&square(0, 9)), Some(18));
+    }
+
+    #[test]
+    fn influence_follows_units() {
+        let hexes = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::PointyUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let mut app = App::new();
+        app.add_plugin(GridPlugin::new(hexes))
+            .add_plugin(InfluencePlugin::<Hexes>::default());
+        let (red, blue) = (Faction(0), Faction(1));
+        let soldier = Influence {
+            strength: 3.0,
+            radius: 2,
+            falloff: Falloff::Linear,
+        };
+        let hex = |q, r| HexCoord { q, r };
+        let knight = app
+            .world
+            .spawn((GridPosition(hex(0, 0)), soldier, red))
+            .id();
+        app.world.spawn((GridPosition(hex(1, 0)), soldier, red));
+        app.world.spawn((GridPosition(hex(4, 0)), soldier, blue));
+        app.update();
+
+        let influences = app.world.resource::<Influences<HexCoord>>();
+        // 3 from the one beside, 2 from the one a cell away
+        assert!(close(influences.influence(&red, &hex(0, 0)), 5.0));
+        assert!(close(influences.influence(&red, &hex(3, 0)), 1.0));
+        assert!(close(influences.influence(&blue, &hex(3, 0)), 2.0));
+        assert!(close(influences.threat(&red, &hex(3, 0)), 2.0));
+        assert_eq!(influences.control(&hex(3, 0)), Some(blue));
+        assert_eq!(influences.control(&hex(8, 0)), None);
+
+        app.world
+            .get_mut::<GridPosition<HexCoord>>(knight)
+            .unwrap()
+            .0 = hex(2, 0);
+        app.update();
+        let influences = app.world.resource::<Influences<HexCoord>>();
+        assert!(close(influences.influence(&red, &hex(0, 0)), 3.0));
+        assert!(close(influences.influence(&red, &hex(3, 0)), 3.0));
+        assert_eq!(influences.control(&hex(3, 0)), Some(red));
+
+        app.world.despawn(knight);
+        app.update();
+        let influences = app.world.resource::<Influences<HexCoord>>();
+        assert!(close(influences.influence(&red, &hex(3, 0)), 1.0));
+        assert_eq!(influences.get(&red).unwrap().layer().len(), 19);
+    }
+}
@@ -0,0 +1,96 @@
+use bevy::{prelude::*, utils::HashMap};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::hash::Hash;
+
+use super::coordinates::Coords;
+
+/// Per-cell data of type `V`, one resource per value type and grid
+#[derive(Resource, Clone)]
+pub struct GridLayer<K: Coords, V>(HashMap<K, V>);
+
+impl<K: Coords + Eq + Hash, V> GridLayer<K, V> {
+    pub fn new() -> GridLayer<K, V> {
+        GridLayer(HashMap::new())
+    }
+
+    pub fn get(&self, coord: &K) -> Option<&V> {
+        self.0.get(coord)
+    }
+
+    pub fn get_mut(&mut self, coord: &K) -> Option<&mut V> {
+        self.0.get_mut(coord)
+    }
+
+    pub fn insert(&mut self, coord: K, value: V) -> Option<V> {
+        self.0.insert(coord, value)
+    }
+
+    pub fn remove(&mut self, coord: &K) -> Option<V> {
+        self.0.remove(coord)
+    }
+
+    pub fn contains(&self, coord: &K) -> bool {
+        self.0.contains_key(coord)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.0.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        self.0.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear()
+    }
+
+    /// Turns a test on cell values into a test on coordinates, cells without
+    /// a value never matching, e.g. `walls.matching(|w| w.opaque)`
+    pub fn matching<'a, F>(&'a self, f: F) -> impl Fn(&K) -> bool + 'a
+    where
+        F: Fn(&V) -> bool + 'a,
+    {
+        move |coord| self.get(coord).is_some_and(&f)
+    }
+}
+
+impl<K: Coords + Eq + Hash, V> Default for GridLayer<K, V> {
+    fn default() -> Self {
+        GridLayer::new()
+    }
+}
+
+impl<K: Coords + Eq + Hash, V> FromIterator<(K, V)> for GridLayer<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        GridLayer(iter.into_iter().collect())
+    }
+}
+
+/// Serialized as a sequence of (coordinate, value) pairs
+#[cfg(feature = "serde")]
+impl<K: Coords + Eq + Hash + Serialize, V: Serialize> Serialize for GridLayer<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, V> Deserialize<'de> for GridLayer<K, V>
+where
+    K: Coords + Eq + Hash + Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<(K, V)>::deserialize(deserializer).map(|cells| cells.into_iter().collect())
+    }
+}
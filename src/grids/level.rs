@@ -0,0 +1,473 @@
+use bevy::prelude::*;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error, fmt, fs, io, path::Path};
+
+use super::{
+    asset::{GridShape, ShapedGrid},
+    coordinates::Coords,
+    layers::GridLayer,
+    primitives::WrapError,
+    spawn_grid, GridConfig, GridLayout, GridMap, GridPlugin,
+};
+
+/// Version of the level format written by this crate
+pub const LEVEL_VERSION: u32 = 2;
+
+/// First bytes of a binary level file
+const MAGIC: &[u8; 4] = b"HALV";
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(io::Error),
+    Ron(ron::Error),
+    Binary(bincode::Error),
+    NotALevel,
+    UnsupportedVersion(u32),
+    WrongPrimitive {
+        expected: &'static str,
+        found: GridShape,
+    },
+    Wrap(WrapError),
+    DuplicateLayer(String),
+    LayerSize {
+        layer: String,
+        expected: usize,
+        found: usize,
+    },
+    InvalidCell {
+        layer: String,
+        cell: String,
+        value: u32,
+        palette: usize,
+    },
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelError::Io(e) => write!(f, "could not access the level file: {e}"),
+            LevelError::Ron(e) => write!(f, "invalid text level: {e}"),
+            LevelError::Binary(e) => write!(f, "invalid binary level: {e}"),
+            LevelError::NotALevel => write!(f, "not a binary level file"),
+            LevelError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported level format version {v} (expected 1..={LEVEL_VERSION})"
+            ),
+            LevelError::WrongPrimitive { expected, found } => {
+                write!(f, "expected a level of {expected}, found {found:?}")
+            }
+            LevelError::Wrap(e) => write!(f, "level grid {e}"),
+            LevelError::DuplicateLayer(layer) => write!(f, "layer {layer:?} appears twice"),
+            LevelError::LayerSize {
+                layer,
+                expected,
+                found,
+            } => write!(
+                f,
+                "layer {layer:?} has {found} cells, the map has {expected}"
+            ),
+            LevelError::InvalidCell {
+                layer,
+                cell,
+                value,
+                palette,
+            } => write!(
+                f,
+                "cell {cell} of layer {layer:?} refers to value {value}, the palette has {palette}"
+            ),
+        }
+    }
+}
+
+impl Error for LevelError {}
+
+impl From<io::Error> for LevelError {
+    fn from(e: io::Error) -> Self {
+        LevelError::Io(e)
+    }
+}
+
+impl From<ron::Error> for LevelError {
+    fn from(e: ron::Error) -> Self {
+        LevelError::Ron(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for LevelError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        LevelError::Ron(e.code)
+    }
+}
+
+impl From<WrapError> for LevelError {
+    fn from(e: WrapError) -> Self {
+        LevelError::Wrap(e)
+    }
+}
+
+impl From<bincode::Error> for LevelError {
+    fn from(e: bincode::Error) -> Self {
+        LevelError::Binary(e)
+    }
+}
+
+/// On-disk description of a map: its grid, its size in offset columns and
+/// rows, and named per-cell values. Levels are stored as RON text or as a
+/// compact binary, and older versions are migrated when read.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Level {
+    pub version: u32,
+    pub grid: GridShape,
+    pub width: u32,
+    pub height: u32,
+    pub layers: Vec<LevelLayer>,
+}
+
+/// Values of a layer, one per cell in the order of `GridLayout::cells`:
+/// 0 for cells without a value, `i` for `palette[i - 1]`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LevelLayer {
+    pub name: String,
+    pub palette: Vec<String>,
+    pub cells: Vec<u32>,
+}
+
+/// Version 1 stored every value in full, empty for cells without one
+#[derive(Deserialize)]
+struct LevelV1 {
+    grid: GridShape,
+    width: u32,
+    height: u32,
+    layers: Vec<(String, Vec<String>)>,
+}
+
+impl From<LevelV1> for Level {
+    fn from(old: LevelV1) -> Self {
+        let layers = old
+            .layers
+            .into_iter()
+            .map(|(name, values)| {
+                let mut palette = Vec::new();
+                let cells = values
+                    .into_iter()
+                    .map(|value| palette_index(&mut palette, Some(value)))
+                    .collect();
+                LevelLayer {
+                    name,
+                    palette,
+                    cells,
+                }
+            })
+            .collect();
+        Level {
+            version: LEVEL_VERSION,
+            grid: old.grid,
+            width: old.width,
+            height: old.height,
+            layers,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+/// Index of `value` in a layer's cells, adding it to the palette if need be
+fn palette_index(palette: &mut Vec<String>, value: Option<String>) -> u32 {
+    match value {
+        None => 0,
+        Some(value) if value.is_empty() => 0,
+        Some(value) => match palette.iter().position(|v| *v == value) {
+            Some(i) => i as u32 + 1,
+            None => {
+                palette.push(value);
+                palette.len() as u32
+            }
+        },
+    }
+}
+
+fn binary() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+/// Grid size and named layers of the level being played
+#[derive(Resource, Clone)]
+pub struct LevelMap<K: Coords> {
+    pub width: u32,
+    pub height: u32,
+    pub layers: BTreeMap<String, GridLayer<K, String>>,
+}
+
+impl Level {
+    /// Level of the grid and the layers of `map`, ignoring values outside of the map
+    pub fn new<T: GridLayout + ShapedGrid>(grid: &T, map: &LevelMap<T::Coord>) -> Level {
+        let cells = grid.cells(map.width, map.height);
+        let layers = map
+            .layers
+            .iter()
+            .map(|(name, layer)| {
+                let mut palette = Vec::new();
+                let cells = cells
+                    .iter()
+                    .map(|c| palette_index(&mut palette, layer.get(c).cloned()))
+                    .collect();
+                LevelLayer {
+                    name: name.clone(),
+                    palette,
+                    cells,
+                }
+            })
+            .collect();
+        Level {
+            version: LEVEL_VERSION,
+            grid: grid.shape(),
+            width: map.width,
+            height: map.height,
+            layers,
+        }
+    }
+
+    /// Level of the `GridConfig<T>` and `LevelMap` resources of `world`, if any
+    pub fn capture<T: GridLayout + ShapedGrid>(world: &World) -> Option<Level> {
+        let grid = world.get_resource::<GridConfig<T>>()?;
+        let map = world.get_resource::<LevelMap<T::Coord>>()?;
+        Some(Level::new(&grid.0, map))
+    }
+
+    /// Checks the level describes a grid of `T` wrapping by sizes it can wrap
+    /// by, and turns its layers into `GridLayer`s, reporting the first bad cell
+    pub fn to_map<T: GridLayout + ShapedGrid>(
+        &self,
+    ) -> Result<(T, LevelMap<T::Coord>), LevelError> {
+        let grid = T::from_shape(&self.grid).ok_or_else(|| LevelError::WrongPrimitive {
+            expected: T::NAME,
+            found: self.grid.clone(),
+        })?;
+        grid.check_wrap()?;
+        let cells = grid.cells(self.width, self.height);
+        let mut layers = BTreeMap::new();
+        for layer in &self.layers {
+            if layer.cells.len() != cells.len() {
+                return Err(LevelError::LayerSize {
+                    layer: layer.name.clone(),
+                    expected: cells.len(),
+                    found: layer.cells.len(),
+                });
+            }
+            let mut values = GridLayer::new();
+            for (coord, &value) in cells.iter().zip(&layer.cells) {
+                if value == 0 {
+                    continue;
+                }
+                let Some(v) = layer.palette.get(value as usize - 1) else {
+                    return Err(LevelError::InvalidCell {
+                        layer: layer.name.clone(),
+                        cell: format!("{coord:?}"),
+                        value,
+                        palette: layer.palette.len(),
+                    });
+                };
+                values.insert(*coord, v.clone());
+            }
+            if layers.insert(layer.name.clone(), values).is_some() {
+                return Err(LevelError::DuplicateLayer(layer.name.clone()));
+            }
+        }
+        let map = LevelMap {
+            width: self.width,
+            height: self.height,
+            layers,
+        };
+        Ok((grid, map))
+    }
+
+    pub fn to_text(&self) -> Result<String, LevelError> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    pub fn from_text(text: &str) -> Result<Level, LevelError> {
+        let header: Header = ron::from_str(text)?;
+        match header.version {
+            1 => Ok(ron::from_str::<LevelV1>(text)?.into()),
+            LEVEL_VERSION => Ok(ron::from_str(text)?),
+            v => Err(LevelError::UnsupportedVersion(v)),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, LevelError> {
+        let mut bytes = MAGIC.to_vec();
+        binary().serialize_into(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Level, LevelError> {
+        let body = bytes.strip_prefix(MAGIC).ok_or(LevelError::NotALevel)?;
+        let header: Header = binary().allow_trailing_bytes().deserialize(body)?;
+        match header.version {
+            1 => {
+                let (_, old): (u32, LevelV1) = binary().deserialize(body)?;
+                Ok(old.into())
+            }
+            LEVEL_VERSION => Ok(binary().deserialize(body)?),
+            v => Err(LevelError::UnsupportedVersion(v)),
+        }
+    }
+
+    /// Writes RON text to `.ron` files and binary to any other
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), LevelError> {
+        let bytes = if is_text(path.as_ref()) {
+            self.to_text()?.into_bytes()
+        } else {
+            self.to_bytes()?
+        };
+        Ok(fs::write(path, bytes)?)
+    }
+
+    /// Reads RON text from `.ron` files and binary from any other
+    pub fn load(path: impl AsRef<Path>) -> Result<Level, LevelError> {
+        let bytes = fs::read(path.as_ref())?;
+        if is_text(path.as_ref()) {
+            Level::from_text(&String::from_utf8_lossy(&bytes))
+        } else {
+            Level::from_bytes(&bytes)
+        }
+    }
+}
+
+fn is_text(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "ron")
+}
+
+/// Spawns the grid of a level through `GridPlugin`, along with its `LevelMap`
+pub struct LevelPlugin<T: GridLayout> {
+    grid: T,
+    map: LevelMap<T::Coord>,
+}
+
+impl<T: GridLayout + ShapedGrid> LevelPlugin<T> {
+    pub fn new(level: &Level) -> Result<Self, LevelError> {
+        let (grid, map) = level.to_map()?;
+        Ok(LevelPlugin { grid, map })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LevelError> {
+        LevelPlugin::new(&Level::load(path)?)
+    }
+}
+
+impl<T: GridLayout + ShapedGrid> Plugin for LevelPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(GridPlugin::new(self.grid.clone()))
+            .insert_resource(self.map.clone())
+            .add_startup_system(spawn_level::<T>);
+    }
+}
+
+/// Spawns the cells of the `LevelMap`
+pub fn spawn_level<T: GridLayout>(
+    mut commands: Commands,
+    grid: Res<GridConfig<T>>,
+    level: Res<LevelMap<T::Coord>>,
+    mut map: ResMut<GridMap<T::Coord>>,
+) {
+    spawn_grid(&mut commands, &grid, (level.width, level.height), &mut map);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::SquareCoord,
+        primitives::{GridAlign, GridWrap, Squares, Triangles},
+    };
+
+    fn squares() -> Squares {
+        Squares {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        }
+    }
+
+    #[test]
+    fn levels_round_trip() {
+        let mut terrain = GridLayer::new();
+        terrain.insert(SquareCoord { q: 0, r: 0 }, "water".to_string());
+        terrain.insert(SquareCoord { q: 1, r: 2 }, "hills".to_string());
+        terrain.insert(SquareCoord { q: 9, r: 9 }, "outside".to_string());
+        let map = LevelMap {
+            width: 3,
+            height: 3,
+            layers: BTreeMap::from([("terrain".to_string(), terrain)]),
+        };
+        let level = Level::new(&squares(), &map);
+        assert_eq!(level.layers[0].palette, ["water", "hills"]);
+
+        for level in [
+            Level::from_text(&level.to_text().unwrap()).unwrap(),
+            Level::from_bytes(&level.to_bytes().unwrap()).unwrap(),
+        ] {
+            let (_, loaded): (Squares, _) = level.to_map().unwrap();
+            let terrain = &loaded.layers["terrain"];
+            assert_eq!(terrain.len(), 2);
+            assert_eq!(terrain.get(&SquareCoord { q: 1, r: 2 }).unwrap(), "hills");
+        }
+    }
+
+    #[test]
+    fn old_levels_are_migrated() {
+        let level = Level::from_text(
+            r#"(
+                version: 1,
+                grid: Squares((size: 1.0, alignment: XY, layer: 0.0)),
+                width: 2,
+                height: 1,
+                layers: [("terrain", ["sand", ""])],
+            )"#,
+        )
+        .unwrap();
+        assert_eq!(level.version, LEVEL_VERSION);
+        assert_eq!(level.layers[0].cells, [1, 0]);
+
+        let error = Level::from_text("(version: 0)").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("unsupported level format version 0 (expected 1..={LEVEL_VERSION})")
+        );
+    }
+
+    #[test]
+    fn bad_cells_are_reported() {
+        let mut level = Level::new(
+            &squares(),
+            &LevelMap::<SquareCoord> {
+                width: 2,
+                height: 2,
+                layers: BTreeMap::from([("terrain".to_string(), GridLayer::new())]),
+            },
+        );
+        level.layers[0].cells[3] = 5;
+        let error = level.to_map::<Squares>().err().unwrap();
+        assert!(error.to_string().contains("SquareCoord { q: 1, r: 1 }"));
+
+        let error = level.to_map::<Triangles>().err().unwrap();
+        assert!(matches!(error, LevelError::WrongPrimitive { .. }));
+        assert!(error
+            .to_string()
+            .starts_with("expected a level of Triangles,"));
+
+        let mut wrapped = squares();
+        wrapped.wrap.width = Some(0);
+        level.grid = wrapped.shape();
+        let error = level.to_map::<Squares>().err().unwrap();
+        assert!(matches!(error, LevelError::Wrap(WrapError::Empty("width"))));
+    }
+}
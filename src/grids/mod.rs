@@ -1,23 +1,128 @@
 use bevy::{prelude::*, utils::HashMap};
 use coordinates::{Coords, TriangleCoord};
 use primitives::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{fmt, hash::Hash};
 
-use self::coordinates::{HexCoord, SquareCoord};
+use self::{
+    coordinates::{HexCoord, SquareCoord},
+    topology::{wrap_around, Topology},
+};
 
+pub mod ascii;
+#[cfg(feature = "serde")]
+pub mod asset;
+pub mod automata;
 pub mod coordinates;
+pub mod edges;
+pub mod export;
+pub mod flow;
+pub mod fog;
+pub mod generation;
+pub mod hierarchy;
+pub mod influence;
+pub mod layers;
+#[cfg(feature = "serde")]
+pub mod level;
+pub mod pathfinding;
 pub mod primitives;
+pub mod regions;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod selection;
+#[cfg(feature = "serde")]
+pub mod snapshot;
+pub mod terrain;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "tiled")]
+pub mod tiled;
+pub mod topology;
+pub mod turns;
+pub mod units;
+pub mod visibility;
+pub mod wfc;
 #[derive(Resource)]
 pub struct GridMap<K: Coords>(HashMap<K, Entity>);
+impl<K: Coords + Eq + Hash> GridMap<K> {
+    pub fn get(&self, coord: &K) -> Option<Entity> {
+        self.0.get(coord).copied()
+    }
+
+    pub fn insert(&mut self, coord: K, entity: Entity) -> Option<Entity> {
+        self.0.insert(coord, entity)
+    }
+
+    pub fn remove(&mut self, coord: &K) -> Option<Entity> {
+        self.0.remove(coord)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Entity)> {
+        self.0.iter()
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, Entity)> + '_ {
+        self.0.drain()
+    }
+}
+
+impl<K: Coords> Default for GridMap<K> {
+    fn default() -> Self {
+        GridMap(HashMap::new())
+    }
+}
+
+/// Cell an entity stands on
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GridPosition<K: Coords>(pub K);
+
+/// Colour of a cell entity before any tinting, e.g. by the fog of war
+#[derive(Component, Clone, Copy, Debug)]
+pub struct CellColor(pub Color);
+
+/// Colour a cell is spawned with, which it returns to once nothing else, such
+/// as its terrain, paints it
+#[derive(Component, Clone, Copy, Debug)]
+pub struct BaseColor(pub Color);
 
 #[derive(Resource)]
 pub struct GridConfig<T: GridPrimitive>(pub T);
-impl GridConfig<Triangles> {
+impl<T: GridLayout> GridConfig<T> {
     pub fn to_mesh(&self) -> Mesh {
         self.0.to_mesh()
     }
-    pub fn to_grid(&self, width: u32, height: u32) -> (Mesh, Vec<TriangleCoord>) {
-        let mesh = self.to_mesh();
-        let mut coordinates = Vec::with_capacity(width as usize * height as usize);
+    pub fn to_grid(&self, width: u32, height: u32) -> (Mesh, Vec<T::Coord>) {
+        (self.to_mesh(), self.0.cells(width, height))
+    }
+}
+
+/// Primitives laying out a rectangular map of cell entities
+pub trait GridLayout:
+    GridPrimitive + Topology<Self::Coord> + Clone + Send + Sync + 'static
+{
+    type Coord: Coords + Copy + Eq + Hash + fmt::Debug + Send + Sync + 'static;
+
+    /// Cells of a map `width` columns by `height` rows large
+    fn cells(&self, width: u32, height: u32) -> Vec<Self::Coord>;
+
+    fn cell_transform(&self, coord: &Self::Coord) -> Transform;
+
+    /// Cell containing a point of the grid plane
+    fn cell_at(&self, pos: Vec3) -> Self::Coord;
+
+    /// Corners of a cell in world space, as drawn by `to_mesh`
+    fn cell_corners(&self, coord: &Self::Coord) -> Vec<Vec3>;
+
+    fn alignment(&self) -> GridAlign;
+}
+
+impl GridLayout for Triangles {
+    type Coord = TriangleCoord;
+
+    fn cells(&self, width: u32, height: u32) -> Vec<TriangleCoord> {
+        let mut coordinates = Vec::with_capacity(2 * width as usize * height as usize);
         for i in 0..width {
             for j in 0..height {
                 coordinates.push(TriangleCoord {
@@ -32,15 +137,33 @@ impl GridConfig<Triangles> {
                 });
             }
         }
-        (mesh, coordinates)
+        coordinates
     }
-}
-impl GridConfig<Squares> {
-    pub fn to_mesh(&self) -> Mesh {
-        self.0.to_mesh()
+
+    fn cell_transform(&self, coord: &TriangleCoord) -> Transform {
+        coord.to_world_pos(*self)
     }
-    pub fn to_grid(&self, width: u32, height: u32) -> (Mesh, Vec<SquareCoord>) {
-        let mesh = self.to_mesh();
+
+    fn cell_at(&self, pos: Vec3) -> TriangleCoord {
+        TriangleCoord::new_from_world_pos(pos, self)
+    }
+
+    fn cell_corners(&self, coord: &TriangleCoord) -> Vec<Vec3> {
+        let transform = self.cell_transform(coord);
+        (0..3)
+            .map(|i| transform.transform_point(self.corner_offset(i)))
+            .collect()
+    }
+
+    fn alignment(&self) -> GridAlign {
+        self.alignment
+    }
+}
+
+impl GridLayout for Squares {
+    type Coord = SquareCoord;
+
+    fn cells(&self, width: u32, height: u32) -> Vec<SquareCoord> {
         let mut coordinates = Vec::with_capacity(width as usize * height as usize);
         for i in 0..width {
             for j in 0..height {
@@ -48,62 +171,151 @@ impl GridConfig<Squares> {
                     q: i as i32,
                     r: j as i32,
                 });
-                coordinates.push(SquareCoord {
-                    q: i as i32,
-                    r: j as i32,
-                });
             }
         }
-        (mesh, coordinates)
+        coordinates
     }
-}
-impl GridConfig<Hexes> {
-    pub fn to_mesh(&self) -> Mesh {
-        self.0.to_mesh()
+
+    fn cell_transform(&self, coord: &SquareCoord) -> Transform {
+        coord.to_world_pos(*self)
     }
-    pub fn to_grid(&self, width: u32, height: u32) -> (Mesh, Vec<HexCoord>) {
-        todo!()
+
+    fn cell_at(&self, pos: Vec3) -> SquareCoord {
+        SquareCoord::new_from_world_pos(pos, self)
+    }
+
+    fn cell_corners(&self, coord: &SquareCoord) -> Vec<Vec3> {
+        let transform = self.cell_transform(coord);
+        (0..4)
+            .map(|i| transform.transform_point(self.corner_offset(i)))
+            .collect()
+    }
+
+    fn alignment(&self) -> GridAlign {
+        self.alignment
     }
 }
 
-#[derive(Clone)]
-pub struct GridPlugin<T: GridPrimitive>(pub T);
-impl GridPlugin<Triangles> {
-    pub fn new(primitive: Triangles) -> GridPlugin<Triangles> {
-        GridPlugin(primitive)
+impl GridLayout for Hexes {
+    type Coord = HexCoord;
+
+    fn cells(&self, width: u32, height: u32) -> Vec<HexCoord> {
+        let mut coordinates = Vec::with_capacity(width as usize * height as usize);
+        for i in 0..width {
+            for j in 0..height {
+                coordinates.push(self.at_offset(i as i32, j as i32));
+            }
+        }
+        coordinates
+    }
+
+    fn cell_transform(&self, coord: &HexCoord) -> Transform {
+        coord.to_world_pos(self)
+    }
+
+    fn cell_at(&self, pos: Vec3) -> HexCoord {
+        HexCoord::new_from_world_pos(pos, self)
+    }
+
+    fn cell_corners(&self, coord: &HexCoord) -> Vec<Vec3> {
+        let transform = self.cell_transform(coord);
+        (0..6)
+            .map(|i| transform.transform_point(self.corner_offset(i)))
+            .collect()
+    }
+
+    fn alignment(&self) -> GridAlign {
+        self.alignment
     }
 }
-impl GridPlugin<Squares> {
-    pub fn new(primitive: Squares) -> GridPlugin<Squares> {
-        GridPlugin(primitive)
+
+/// Despawns the cells of `map` and spawns a `width` by `height` grid in their
+/// place. Cells are given their meshes by the `GridRenderPlugin`.
+pub fn spawn_grid<T: GridLayout>(
+    commands: &mut Commands,
+    grid: &GridConfig<T>,
+    (width, height): (u32, u32),
+    map: &mut GridMap<T::Coord>,
+) {
+    for (_, cell) in map.drain() {
+        commands.entity(cell).despawn_recursive();
+    }
+    for c in grid.0.cells(width, height) {
+        let cell = commands
+            .spawn((
+                TransformBundle::from_transform(grid.0.cell_transform(&c)),
+                CellColor(Color::GREEN),
+                BaseColor(Color::GREEN),
+            ))
+            .id();
+        map.insert(c, cell);
     }
 }
-impl GridPlugin<Hexes> {
-    pub fn new(primitive: Hexes) -> GridPlugin<Hexes> {
+
+/// Grid logic: its `GridConfig` and `GridMap`, without any rendering, so it
+/// also runs in headless apps built on `MinimalPlugins`
+#[derive(Clone)]
+pub struct GridPlugin<T: GridPrimitive>(pub T);
+impl<T: GridPrimitive> GridPlugin<T> {
+    pub fn new(primitive: T) -> GridPlugin<T> {
         GridPlugin(primitive)
     }
 }
-impl Plugin for GridPlugin<Triangles> {
+impl<T: GridLayout> Plugin for GridPlugin<T> {
     fn build(&self, app: &mut App) {
-        let resource = GridMap::<TriangleCoord>(HashMap::new());
-        let object = self.0.clone();
-        app.insert_resource(resource);
-        app.insert_resource(GridConfig(object));
+        app.insert_resource(GridConfig(self.0.clone()));
+        add_grid_systems::<T>(app);
     }
 }
-impl Plugin for GridPlugin<Squares> {
-    fn build(&self, app: &mut App) {
-        let resource = GridMap::<SquareCoord>(HashMap::new());
-        let object = self.0.clone();
-        app.insert_resource(resource);
-        app.insert_resource(GridConfig(object));
-    }
+
+/// Cell map and systems shared by every way of configuring a grid, running
+/// once a `GridConfig<T>` is available
+fn add_grid_systems<T: GridLayout>(app: &mut App) {
+    app.insert_resource(GridMap::<T::Coord>::default())
+        .add_system(wrap_around::<T::Coord, T>.run_if(resource_exists::<GridConfig<T>>()));
 }
-impl Plugin for GridPlugin<Hexes> {
-    fn build(&self, app: &mut App) {
-        let resource = GridMap::<HexCoord>(HashMap::new());
-        let object = self.0.clone();
-        app.insert_resource(resource);
-        app.insert_resource(GridConfig(object));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::HexOrientation,
+        fog::{Faction, FogOfWar, FogOfWarPlugin, FogState, Vision},
+        pathfinding::find_path,
+        testing::spawn_map,
+    };
+
+    #[test]
+    fn grids_run_headless() {
+        let hexes = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::FlatUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(GridPlugin::new(hexes.clone()))
+            .add_plugin(FogOfWarPlugin::<HexCoord>::default())
+            .add_startup_system(spawn_map::<Hexes>(8, 8));
+        let red = Faction(0);
+        app.world.spawn((
+            GridPosition(HexCoord { q: 1, r: 1 }),
+            Vision { radius: 2 },
+            red,
+        ));
+        app.update();
+
+        let map = app.world.resource::<GridMap<HexCoord>>();
+        assert_eq!(map.iter().count(), 64);
+        let cell = map.get(&HexCoord { q: 1, r: 1 }).unwrap();
+        assert!(app.world.get::<Transform>(cell).is_some());
+        let fog = app.world.resource::<FogOfWar<HexCoord>>();
+        assert_eq!(fog.state(&red, &HexCoord { q: 1, r: 1 }), FogState::Visible);
+
+        let far = hexes.at_offset(7, 7);
+        let path = find_path(&hexes, HexCoord::ZERO, far, |c| map.get(c).map(|_| 1));
+        assert_eq!(path.unwrap().cost, HexCoord::ZERO.distance(&far));
     }
 }
@@ -0,0 +1,178 @@
+use bevy::utils::HashMap;
+use std::{cmp::Ordering, collections::BinaryHeap, hash::Hash};
+
+use super::{coordinates::Coords, topology::Topology};
+
+/// Cells from start to goal, both included, and the cost of walking them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Path<K> {
+    pub cells: Vec<K>,
+    pub cost: u32,
+}
+
+/// Cell waiting in the open set of a search, popped cheapest first
+pub(crate) struct Step<K> {
+    pub(crate) estimate: u32,
+    pub(crate) cell: K,
+}
+
+impl<K> Eq for Step<K> {}
+impl<K> PartialEq for Step<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+impl<K> Ord for Step<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // lowest estimate first out of the max-heap
+        other.estimate.cmp(&self.estimate)
+    }
+}
+impl<K> PartialOrd for Step<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A* search from `start` to `goal` through the neighbours of `grid`.
+/// `cost` gives the price of entering a cell, at least 1, or `None` if it
+/// cannot be entered.
+pub fn find_path<K, T, F>(grid: &T, start: K, goal: K, cost: F) -> Option<Path<K>>
+where
+    K: Coords + Copy + Eq + Hash,
+    T: Topology<K>,
+    F: Fn(&K) -> Option<u32>,
+{
+    let (start, goal) = (grid.normalize(&start), grid.normalize(&goal));
+    let mut came_from: HashMap<K, K> = HashMap::new();
+    let mut spent: HashMap<K, u32> = HashMap::new();
+    let mut open = BinaryHeap::new();
+    spent.insert(start, 0);
+    open.push(Step {
+        estimate: grid.distance(&start, &goal),
+        cell: start,
+    });
+
+    while let Some(Step { estimate, cell }) = open.pop() {
+        let so_far = spent[&cell];
+        if cell == goal {
+            let mut cells = vec![goal];
+            while let Some(previous) = came_from.get(cells.last().unwrap()) {
+                cells.push(*previous);
+            }
+            cells.reverse();
+            return Some(Path {
+                cells,
+                cost: so_far,
+            });
+        }
+        if estimate > so_far + grid.distance(&cell, &goal) {
+            continue; // outdated entry, the cell was reached cheaper since
+        }
+        for n in grid.neighbours(&cell) {
+            let Some(step) = cost(&n) else {
+                continue;
+            };
+            let total = so_far + step;
+            if spent.get(&n).is_none_or(|s| total < *s) {
+                spent.insert(n, total);
+                came_from.insert(n, cell);
+                open.push(Step {
+                    estimate: total + grid.distance(&n, &goal),
+                    cell: n,
+                });
+            }
+        }
+    }
+    None
+}
+
+/// Cells reachable from `start` for at most `budget`, with the cheapest cost
+/// of reaching each, `start` included for nothing. `cost` is as for `find_path`.
+pub fn reachable<K, T, F>(grid: &T, start: K, budget: u32, cost: F) -> HashMap<K, u32>
+where
+    K: Coords + Copy + Eq + Hash,
+    T: Topology<K>,
+    F: Fn(&K) -> Option<u32>,
+{
+    let start = grid.normalize(&start);
+    let mut spent: HashMap<K, u32> = HashMap::new();
+    let mut open = BinaryHeap::new();
+    spent.insert(start, 0);
+    open.push(Step {
+        estimate: 0,
+        cell: start,
+    });
+
+    while let Some(Step { estimate, cell }) = open.pop() {
+        if estimate > spent[&cell] {
+            continue;
+        }
+        for n in grid.neighbours(&cell) {
+            let Some(step) = cost(&n) else {
+                continue;
+            };
+            let total = estimate + step;
+            if total <= budget && spent.get(&n).is_none_or(|s| total < *s) {
+                spent.insert(n, total);
+                open.push(Step {
+                    estimate: total,
+                    cell: n,
+                });
+            }
+        }
+    }
+    spent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::{HexCoord, HexOrientation, SquareCoord},
+        primitives::{GridAlign, GridWrap, Hexes, Squares},
+    };
+
+    #[test]
+    fn paths_go_around_walls_and_across_seams() {
+        let mut grid = Squares {
+            size: 1.0,
+            alignment: GridAlign::XY,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let wall = |c: &SquareCoord| c.q == 2 && c.r < 3;
+        let in_map = |c: &SquareCoord| (0..10).contains(&c.q) && (0..5).contains(&c.r);
+        let cost = |c: &SquareCoord| (in_map(c) && !wall(c)).then_some(1);
+        let (start, goal) = (SquareCoord { q: 0, r: 0 }, SquareCoord { q: 4, r: 0 });
+
+        let path = find_path(&grid, start, goal, cost).unwrap();
+        assert_eq!(path.cost, 10);
+        assert!(!path.cells.iter().any(wall));
+
+        grid.wrap.width = Some(10);
+        let path = find_path(&grid, start, goal, cost).unwrap();
+        assert_eq!(path.cost, 6);
+        assert!(path.cells.contains(&SquareCoord { q: 9, r: 0 }));
+    }
+
+    #[test]
+    fn reachable_cells_fit_the_budget() {
+        let grid = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::PointyUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let swamp = HexCoord { q: 1, r: 0 };
+        let cost = |c: &HexCoord| Some(if *c == swamp { 3 } else { 1 });
+        let cells = reachable(&grid, HexCoord::ZERO, 2, cost);
+        // two rings of hexes but the swamp and the one behind it
+        assert_eq!(cells.len(), 17);
+        assert!(!cells.contains_key(&swamp));
+        assert!(!cells.contains_key(&HexCoord { q: 2, r: 0 }));
+        assert_eq!(cells[&HexCoord { q: 2, r: -1 }], 2);
+        assert_eq!(cells[&HexCoord::ZERO], 0);
+    }
+}
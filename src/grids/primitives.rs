@@ -2,8 +2,14 @@ use bevy::{
     prelude::*,
     render::{mesh::Indices, render_resource::PrimitiveTopology},
 };
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt};
 
-use super::coordinates::{HexOrientation, TriangleNeighbours};
+use super::{
+    coordinates::{HexOrientation, TriangleNeighbours},
+    GridLayout,
+};
 
 // TRAIT
 pub trait GridPrimitive {
@@ -15,19 +21,30 @@ pub trait GridPrimitive {
 
 // PRIMITIVES: TRIANGLE
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Triangles {
     pub size: f32,
     pub alignment: GridAlign,
     pub neighbors: TriangleNeighbours,
     pub layer: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub wrap: GridWrap,
+}
+impl Triangles {
+    /// Offset of the i-th corner from the centre of a triangle pointing up:
+    /// the top one, then bottom left and bottom right
+    pub fn corner_offset(&self, i: usize) -> Vec3 {
+        corner_pos(i, 120.0, 90.0, self.size, &self.alignment)
+    }
 }
+
 impl GridPrimitive for Triangles {
     fn to_mesh(&self) -> Mesh {
         let mut vectors = Vec::with_capacity(3);
         let indices = vec![0, 1, 3, 0, 3, 2];
 
         for i in 0..3 {
-            let vec3d_pos = corner_pos(i, 120.0, 90.0, self.size, &self.alignment);
+            let vec3d_pos = self.corner_offset(i);
             vectors.push([vec3d_pos.x, vec3d_pos.y, vec3d_pos.z]);
         }
         let vec1 = vectors.get(1).unwrap();
@@ -51,8 +68,8 @@ impl GridPrimitive for Triangles {
         mesh.set_indices(Some(Indices::U32(indices)));
         mesh
     }
-    fn to_grid(&self, grid_width: u32, grid_height: u32) -> (Mesh, Vec<Vec3>) {
-        todo!()
+    fn to_grid(&self, width: u32, height: u32) -> (Mesh, Vec<Vec3>) {
+        cell_positions(self, width, height)
     }
 
     fn width(&self) -> f32 {
@@ -66,22 +83,32 @@ impl GridPrimitive for Triangles {
 
 // PRIMITIVES: SQUARES
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Squares {
     pub size: f32,
     pub alignment: GridAlign,
     pub layer: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub wrap: GridWrap,
+}
+
+impl Squares {
+    /// Offset of the i-th corner from the centre, counter-clockwise from North East
+    pub fn corner_offset(&self, i: usize) -> Vec3 {
+        corner_pos(i, 90.0, 45.0, self.size, &self.alignment)
+    }
 }
 
 impl GridPrimitive for Squares {
     fn to_grid(&self, width: u32, height: u32) -> (Mesh, Vec<Vec3>) {
-        todo!()
+        cell_positions(self, width, height)
     }
 
     fn to_mesh(&self) -> Mesh {
         let mut vectors = Vec::with_capacity(4);
         let indices = vec![0, 1, 2, 0, 2, 3];
         for i in 0..4 {
-            let vec3d_pos = corner_pos(i, 90.0, 45.0, self.size, &self.alignment);
+            let vec3d_pos = self.corner_offset(i);
             vectors.push([vec3d_pos.x, vec3d_pos.y, vec3d_pos.z]);
         }
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
@@ -99,21 +126,36 @@ impl GridPrimitive for Squares {
     }
 
     fn width(&self) -> f32 {
-        todo!()
+        (2.0 * self.size.powi(2)).sqrt()
     }
 
     fn height(&self) -> f32 {
-        todo!()
+        self.width()
     }
 }
 
 // PRIMITIVES: HEXAGON
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Hexes {
     pub size: f32,
     pub alignment: GridAlign,
     pub orientation: HexOrientation,
     pub layer: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub wrap: GridWrap,
+}
+
+impl Hexes {
+    /// Offset of the i-th corner from the centre, corner i lying between the
+    /// neighbours in `HexCoord::DIRECTIONS[i]` and `HexCoord::DIRECTIONS[i + 1]`
+    pub fn corner_offset(&self, i: usize) -> Vec3 {
+        let offset = match self.orientation {
+            HexOrientation::PointyUp => 30.0,
+            HexOrientation::FlatUp => 0.0,
+        };
+        corner_pos(i, 60.0, offset, self.size, &self.alignment)
+    }
 }
 
 impl GridPrimitive for Hexes {
@@ -121,13 +163,8 @@ impl GridPrimitive for Hexes {
         let mut vectors = Vec::with_capacity(8);
         vectors.push([0.0, 0.0, 0.0]);
         let mut indices = Vec::new();
-        let offset = match self.orientation {
-            HexOrientation::PointyUp => 30.0,
-            HexOrientation::FlatUp => 0.0,
-        };
         for i in 0..6 {
-            let vec3d_pos = corner_pos(i, 60.0, offset, self.size, &self.alignment);
-            dbg!(&vec3d_pos);
+            let vec3d_pos = self.corner_offset(i);
             vectors.push([vec3d_pos.x, vec3d_pos.y, vec3d_pos.z]);
             indices.push(0);
             indices.push(i as u32 + 1);
@@ -152,25 +189,110 @@ impl GridPrimitive for Hexes {
     }
 
     fn to_grid(&self, width: u32, height: u32) -> (Mesh, Vec<Vec3>) {
-        todo!()
+        cell_positions(self, width, height)
     }
 
     fn width(&self) -> f32 {
-        todo!()
+        match self.orientation {
+            HexOrientation::PointyUp => 3_f32.sqrt() * self.size,
+            HexOrientation::FlatUp => 2.0 * self.size,
+        }
     }
 
     fn height(&self) -> f32 {
-        todo!()
+        match self.orientation {
+            HexOrientation::PointyUp => 2.0 * self.size,
+            HexOrientation::FlatUp => 3_f32.sqrt() * self.size,
+        }
     }
 }
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum GridAlign {
     XY,
     XZ,
 }
 
+impl GridAlign {
+    /// Coordinates of a world position within the grid plane
+    pub fn to_plane(&self, pos: Vec3) -> Vec2 {
+        match self {
+            GridAlign::XY => pos.truncate(),
+            GridAlign::XZ => Vec2::new(pos.x, pos.z),
+        }
+    }
+
+    /// Up out of the grid plane
+    pub fn normal(&self) -> Vec3 {
+        match self {
+            GridAlign::XY => Vec3::Z,
+            GridAlign::XZ => Vec3::Y,
+        }
+    }
+}
+
+/// Map size, in cells, after which coordinates wrap around. Hex grids wrap
+/// their offset columns and rows; grids of triangles wrap pairs of triangles.
+/// Wrapping rows of triangles, and rows of pointy or columns of flat hexes,
+/// takes an even size. Sizes read from files are checked by `validate`.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GridWrap {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl GridWrap {
+    /// Checks each axis wraps after at least one cell, and after an even
+    /// number of them on the axes of `even`, width then height
+    pub fn validate(&self, even: (bool, bool)) -> Result<(), WrapError> {
+        let axes = [
+            ("width", self.width, even.0),
+            ("height", self.height, even.1),
+        ];
+        for (axis, size, even) in axes {
+            match size {
+                Some(0) => return Err(WrapError::Empty(axis)),
+                Some(size) if even && size % 2 != 0 => return Err(WrapError::Odd(axis, size)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wrap size, along the named axis, a grid cannot wrap by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WrapError {
+    Empty(&'static str),
+    Odd(&'static str, u32),
+}
+
+impl fmt::Display for WrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WrapError::Empty(axis) => write!(f, "cannot wrap its {axis} after 0 cells"),
+            WrapError::Odd(axis, size) => write!(
+                f,
+                "cannot wrap its {axis} after {size} cells, only after an even number"
+            ),
+        }
+    }
+}
+
+impl Error for WrapError {}
+
 // generic funcions
+fn cell_positions<T: GridLayout>(grid: &T, width: u32, height: u32) -> (Mesh, Vec<Vec3>) {
+    let positions = grid
+        .cells(width, height)
+        .iter()
+        .map(|c| grid.cell_pos(c))
+        .collect();
+    (grid.to_mesh(), positions)
+}
+
 fn corner_pos(i: usize, angle: f32, offset: f32, size: f32, grid_align: &GridAlign) -> Vec3 {
     let angle = angle.to_radians() * i as f32 + offset.to_radians();
     let (sin, cos) = angle.sin_cos();
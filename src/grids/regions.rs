@@ -0,0 +1,141 @@
+use bevy::utils::HashSet;
+use std::hash::Hash;
+
+use super::{coordinates::Coords, layers::GridLayer};
+
+/// Edge between a cell of a region and a neighbour outside of it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CellEdge<K> {
+    pub inside: K,
+    pub outside: K,
+}
+
+/// Every cell reachable from `start` through neighbours matching `pred`,
+/// `start` included if it matches. The predicate has to bound the fill.
+pub fn flood_fill<K, F>(start: K, pred: F) -> HashSet<K>
+where
+    K: Coords + Copy + Eq + Hash,
+    F: Fn(&K) -> bool,
+{
+    let mut region = HashSet::new();
+    if !pred(&start) {
+        return region;
+    }
+    region.insert(start);
+    let mut stack = vec![start];
+    while let Some(cell) = stack.pop() {
+        for n in cell.neighbours() {
+            if !region.contains(&n) && pred(&n) {
+                region.insert(n);
+                stack.push(n);
+            }
+        }
+    }
+    region
+}
+
+/// Splits the cells of `layer` into regions of neighbours whose values are
+/// `connected`, e.g. `|a, b| a == b` to get contiguous lakes, forests, etc.
+pub fn connected_regions<K, V, F>(layer: &GridLayer<K, V>, connected: F) -> Vec<HashSet<K>>
+where
+    K: Coords + Copy + Eq + Hash,
+    F: Fn(&V, &V) -> bool,
+{
+    let mut seen: HashSet<K> = HashSet::new();
+    let mut regions = Vec::new();
+    for (coord, value) in layer.iter() {
+        if seen.contains(coord) {
+            continue;
+        }
+        let region = flood_fill(*coord, |c| {
+            layer.get(c).is_some_and(|v| connected(value, v))
+        });
+        seen.extend(region.iter().copied());
+        regions.push(region);
+    }
+    regions
+}
+
+/// Region index of every cell of `layer`, indices matching `connected_regions`
+pub fn label_regions<K, V, F>(layer: &GridLayer<K, V>, connected: F) -> GridLayer<K, usize>
+where
+    K: Coords + Copy + Eq + Hash,
+    F: Fn(&V, &V) -> bool,
+{
+    connected_regions(layer, connected)
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, region)| region.into_iter().map(move |c| (c, i)))
+        .collect()
+}
+
+/// Edges separating `region` from the rest of the grid
+pub fn region_border<K>(region: &HashSet<K>) -> Vec<CellEdge<K>>
+where
+    K: Coords + Copy + Eq + Hash,
+{
+    region
+        .iter()
+        .flat_map(|inside| {
+            inside
+                .neighbours()
+                .into_iter()
+                .filter(|n| !region.contains(n))
+                .map(|outside| CellEdge {
+                    inside: *inside,
+                    outside,
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::coordinates::{HexCoord, SquareCoord};
+
+    #[derive(PartialEq)]
+    enum Tile {
+        Land,
+        Water,
+    }
+
+    #[test]
+    fn lakes_are_separate_regions() {
+        let mut layer = GridLayer::new();
+        for q in 0..5 {
+            for r in 0..3 {
+                let tile = if q == 2 { Tile::Land } else { Tile::Water };
+                layer.insert(SquareCoord { q, r }, tile);
+            }
+        }
+        let lakes: Vec<_> = connected_regions(&layer, |a, b| a == b)
+            .into_iter()
+            .filter(|region| {
+                let cell = region.iter().next().unwrap();
+                layer.get(cell) == Some(&Tile::Water)
+            })
+            .collect();
+        assert_eq!(lakes.len(), 2);
+        assert!(lakes.iter().all(|lake| lake.len() == 6));
+
+        let labels = label_regions(&layer, |a, b| a == b);
+        assert_eq!(
+            labels.get(&SquareCoord { q: 0, r: 0 }),
+            labels.get(&SquareCoord { q: 1, r: 2 })
+        );
+        assert_ne!(
+            labels.get(&SquareCoord { q: 0, r: 0 }),
+            labels.get(&SquareCoord { q: 4, r: 0 })
+        );
+    }
+
+    #[test]
+    fn border_of_a_hex_ring() {
+        let region = flood_fill(HexCoord::ZERO, |c| c.magnitude() <= 1);
+        assert_eq!(region.len(), 7);
+        let border = region_border(&region);
+        assert_eq!(border.len(), 18);
+        assert!(border.iter().all(|e| e.outside.magnitude() == 2));
+    }
+}
@@ -0,0 +1,142 @@
+use bevy::prelude::*;
+use std::{hash::Hash, marker::PhantomData};
+
+use super::{
+    coordinates::Coords,
+    fog::{render_fog, update_fog, FogOfWar},
+    selection::Selection,
+    terrain::CellTexture,
+    topology::{mirror_seams, sync_seam_copies},
+    turns::Reachable,
+    visibility::FieldOfView,
+    CellColor, GridConfig, GridLayout, GridMap,
+};
+
+/// Draws the cells of the grid of `T`, the copies across its seams, its fog
+/// of war and its selected and reachable cells. Only built with the `render`
+/// feature, which apps without a window or GPU leave out.
+pub struct GridRenderPlugin<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for GridRenderPlugin<T> {
+    fn default() -> Self {
+        GridRenderPlugin(PhantomData)
+    }
+}
+
+impl<T: GridLayout> Plugin for GridRenderPlugin<T>
+where
+    T::Coord: FieldOfView,
+{
+    fn build(&self, app: &mut App) {
+        let configured = resource_exists::<GridConfig<T>>;
+        app.add_system(mesh_cells::<T>.run_if(configured()))
+            .add_system(mirror_seams::<T::Coord, T>.run_if(configured()))
+            .add_system(sync_seam_copies.after(mirror_seams::<T::Coord, T>))
+            .add_system(paint_cells.after(mesh_cells::<T>))
+            .add_system(
+                render_fog::<T::Coord>
+                    .after(update_fog::<T::Coord>)
+                    .after(paint_cells)
+                    .run_if(resource_exists::<FogOfWar<T::Coord>>()),
+            )
+            .add_system(render_highlights::<T::Coord>.after(mesh_cells::<T>));
+    }
+}
+
+/// Gives a mesh and a material of its `CellColor` to every new cell, and a
+/// new mesh to all of them when the `GridConfig` changes
+pub fn mesh_cells<T: GridLayout>(
+    mut commands: Commands,
+    grid: Res<GridConfig<T>>,
+    map: Res<GridMap<T::Coord>>,
+    mut mesh: Local<Option<Handle<Mesh>>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    cells: Query<(&CellColor, Option<&Handle<Mesh>>)>,
+) {
+    let remesh = grid.is_changed() || mesh.is_none();
+    if remesh {
+        *mesh = Some(meshes.add(grid.to_mesh()));
+    } else if !map.is_changed() {
+        return;
+    }
+    let mesh = mesh.as_ref().unwrap();
+    for (_, entity) in map.iter() {
+        let Ok((color, current)) = cells.get(*entity) else {
+            continue;
+        };
+        if current.is_some() && !remesh {
+            continue;
+        }
+        commands.entity(*entity).insert((
+            mesh.clone(),
+            materials.add(StandardMaterial {
+                base_color: color.0,
+                ..default()
+            }),
+            VisibilityBundle::default(),
+        ));
+    }
+}
+
+/// Cells whose colour or texture changed, or who have a new material
+type Repainted = Or<(
+    Changed<CellColor>,
+    Changed<CellTexture>,
+    Changed<Handle<StandardMaterial>>,
+)>;
+
+/// Carries changes of `CellColor` and `CellTexture` over to the materials of
+/// the cells, under any fog of war
+pub fn paint_cells(
+    cells: Query<(&CellColor, Option<&CellTexture>, &Handle<StandardMaterial>), Repainted>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (color, texture, handle) in cells.iter() {
+        let Some(material) = materials.get_mut(handle) else {
+            continue;
+        };
+        material.base_color = color.0;
+        material.base_color_texture = texture.map(|t| t.0.clone());
+    }
+}
+
+/// Glow of selected cells
+const SELECTED_GLOW: Color = Color::rgb(0.6, 0.5, 0.1);
+/// Glow of the cells the active unit can reach
+const REACHABLE_GLOW: Color = Color::rgb(0.05, 0.2, 0.4);
+
+/// Lights up the selected and reachable cells, again whenever cells get new
+/// materials
+pub fn render_highlights<K: Coords + Copy + Eq + Hash + Send + Sync + 'static>(
+    selection: Option<Res<Selection<K>>>,
+    reachable: Option<Res<Reachable<K>>>,
+    map: Res<GridMap<K>>,
+    fresh: Query<(), Changed<Handle<StandardMaterial>>>,
+    cells: Query<&Handle<StandardMaterial>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if selection.is_none() && reachable.is_none() {
+        return;
+    }
+    let changed = selection.as_ref().is_some_and(|s| s.is_changed())
+        || reachable.as_ref().is_some_and(|r| r.is_changed());
+    if !changed && fresh.is_empty() {
+        return;
+    }
+    for (coord, entity) in map.iter() {
+        let Ok(handle) = cells.get(*entity) else {
+            continue;
+        };
+        let glow = if selection.as_ref().is_some_and(|s| s.contains(coord)) {
+            SELECTED_GLOW
+        } else if reachable.as_ref().is_some_and(|r| r.contains(coord)) {
+            REACHABLE_GLOW
+        } else {
+            Color::BLACK
+        };
+        if materials.get(handle).is_some_and(|m| m.emissive != glow) {
+            materials.get_mut(handle).unwrap().emissive = glow;
+        }
+    }
+}
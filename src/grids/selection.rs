@@ -0,0 +1,436 @@
+#[cfg(feature = "render")]
+use bevy::window::PrimaryWindow;
+use bevy::{prelude::*, utils::HashSet};
+use std::{hash::Hash, marker::PhantomData};
+
+use super::{coordinates::Coords, GridConfig, GridLayout, GridMap};
+
+/// Point of the grid plane and cell under the mouse cursor, if any
+#[derive(Resource, Clone, Debug)]
+pub struct GridCursor<K: Coords> {
+    pub world: Option<Vec3>,
+    pub cell: Option<K>,
+}
+
+impl<K: Coords> Default for GridCursor<K> {
+    fn default() -> Self {
+        GridCursor {
+            world: None,
+            cell: None,
+        }
+    }
+}
+
+/// Selected cells
+#[derive(Resource, Clone, Debug)]
+pub struct Selection<K: Coords>(HashSet<K>);
+
+impl<K: Coords + Copy + Eq + Hash> Selection<K> {
+    pub fn contains(&self, coord: &K) -> bool {
+        self.0.contains(coord)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &K> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Selects `cells` the way `mode` says, returning what changed
+    pub fn apply(
+        &mut self,
+        cells: impl IntoIterator<Item = K>,
+        mode: SelectMode,
+    ) -> SelectionChanged<K> {
+        let cells: HashSet<K> = cells.into_iter().collect();
+        let mut changed = SelectionChanged {
+            added: Vec::new(),
+            removed: Vec::new(),
+        };
+        if mode == SelectMode::Replace {
+            changed.removed = self.0.difference(&cells).copied().collect();
+        }
+        for cell in cells {
+            if !self.0.contains(&cell) {
+                changed.added.push(cell);
+            } else if mode == SelectMode::Toggle {
+                changed.removed.push(cell);
+            }
+        }
+        for cell in &changed.removed {
+            self.0.remove(cell);
+        }
+        self.0.extend(changed.added.iter().copied());
+        changed
+    }
+
+    pub fn clear(&mut self) -> SelectionChanged<K> {
+        self.apply([], SelectMode::Replace)
+    }
+}
+
+impl<K: Coords> Default for Selection<K> {
+    fn default() -> Self {
+        Selection(HashSet::new())
+    }
+}
+
+/// Cells that joined and left the `Selection` at once
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelectionChanged<K> {
+    pub added: Vec<K>,
+    pub removed: Vec<K>,
+}
+
+impl<K> SelectionChanged<K> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// How clicked or dragged cells change the `Selection`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectMode {
+    /// Only those cells are selected, as with a plain click
+    Replace,
+    /// Those cells are selected too, as with shift held
+    Add,
+    /// Those cells flip in or out of the selection, as with ctrl held
+    Toggle,
+}
+
+impl SelectMode {
+    pub fn from_keys(keys: &Input<KeyCode>) -> SelectMode {
+        if keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+            SelectMode::Toggle
+        } else if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+            SelectMode::Add
+        } else {
+            SelectMode::Replace
+        }
+    }
+}
+
+/// Cells a drag of the mouse selects
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DragShape {
+    /// Cells centred in the rectangle of the grid plane from where the drag
+    /// started to where it ended
+    #[default]
+    Rectangle,
+    /// Cells centred in the area enclosed by the path of the cursor
+    Lasso,
+    /// Cells as many steps away from the cell the drag started on as the one
+    /// it ended on, or fewer: a hexagon of hexes, a diamond of squares
+    Cells,
+}
+
+/// Mouse selection of the cells of the grid of `T`: click a cell to select it,
+/// shift-click to add it, ctrl-click to toggle it, or drag across cells to do
+/// the same to all of them at once. With the `render` feature the cursor is
+/// tracked through the window and the `GridRenderPlugin` highlights the
+/// selection; headless apps move the `GridCursor` themselves.
+pub struct SelectionPlugin<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for SelectionPlugin<T> {
+    fn default() -> Self {
+        SelectionPlugin(PhantomData)
+    }
+}
+
+impl<T: GridLayout> Plugin for SelectionPlugin<T> {
+    fn build(&self, app: &mut App) {
+        let configured = resource_exists::<GridConfig<T>>;
+        app.insert_resource(GridCursor::<T::Coord>::default())
+            .insert_resource(Selection::<T::Coord>::default())
+            .init_resource::<DragShape>()
+            .add_event::<SelectionChanged<T::Coord>>()
+            .add_system(select_cells::<T>.run_if(configured()));
+        #[cfg(feature = "render")]
+        app.add_system(
+            track_cursor::<T>
+                .before(select_cells::<T>)
+                .run_if(configured()),
+        );
+    }
+}
+
+/// Casts the cursor of the primary window through the first active camera
+/// onto the grid plane
+#[cfg(feature = "render")]
+pub fn track_cursor<T: GridLayout>(
+    grid: Res<GridConfig<T>>,
+    map: Res<GridMap<T::Coord>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut cursor: ResMut<GridCursor<T::Coord>>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let normal = grid.0.alignment().normal();
+    let origin = grid.0.cell_pos(&T::Coord::ZERO);
+    let world = window
+        .cursor_position()
+        .and_then(|screen| {
+            cameras
+                .iter()
+                .filter(|(camera, _)| camera.is_active)
+                .find_map(|(camera, transform)| camera.viewport_to_world(transform, screen))
+        })
+        .and_then(|ray| {
+            ray.intersect_plane(origin, normal)
+                .map(|distance| ray.get_point(distance))
+        });
+    let cell = world
+        .map(|pos| grid.0.normalize(&grid.0.cell_at(pos)))
+        .filter(|cell| map.get(cell).is_some());
+    if cursor.world != world || cursor.cell != cell {
+        *cursor = GridCursor { world, cell };
+    }
+}
+
+/// Drag under way, from the cell it started on
+pub struct Drag<K> {
+    start: Option<K>,
+    /// Last cell of the map the cursor was over
+    end: Option<K>,
+    /// Cursor positions on the grid plane
+    path: Vec<Vec2>,
+    dragging: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn select_cells<T: GridLayout>(
+    grid: Res<GridConfig<T>>,
+    map: Res<GridMap<T::Coord>>,
+    cursor: Res<GridCursor<T::Coord>>,
+    shape: Res<DragShape>,
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mut drag: Local<Option<Drag<T::Coord>>>,
+    mut selection: ResMut<Selection<T::Coord>>,
+    mut changes: EventWriter<SelectionChanged<T::Coord>>,
+) {
+    let alignment = grid.0.alignment();
+    if buttons.just_pressed(MouseButton::Left) {
+        *drag = cursor.world.map(|pos| Drag {
+            start: cursor.cell,
+            end: cursor.cell,
+            path: vec![alignment.to_plane(pos)],
+            dragging: false,
+        });
+    }
+    let Some(current) = drag.as_mut() else {
+        return;
+    };
+    if let Some(pos) = cursor.world.map(|pos| alignment.to_plane(pos)) {
+        // a drag starts once the cursor leaves the cell it was pressed on
+        current.dragging |= cursor.cell != current.start;
+        current.end = cursor.cell.or(current.end);
+        if current.path.last() != Some(&pos) {
+            current.path.push(pos);
+        }
+    }
+    if !buttons.just_released(MouseButton::Left) {
+        return;
+    }
+    let Some(Drag {
+        start,
+        end,
+        path,
+        dragging,
+    }) = drag.take()
+    else {
+        return;
+    };
+    let cells: Vec<T::Coord> = if dragging {
+        let inside = |cell: &T::Coord| {
+            let pos = alignment.to_plane(grid.0.cell_pos(cell));
+            match *shape {
+                DragShape::Rectangle => {
+                    let (from, to) = (path[0], path[path.len() - 1]);
+                    let (min, max) = (from.min(to), from.max(to));
+                    pos.cmpge(min).all() && pos.cmple(max).all()
+                }
+                DragShape::Lasso => encloses(&path, pos),
+                DragShape::Cells => match (start, end) {
+                    (Some(start), Some(end)) => {
+                        grid.0.distance(&start, cell) <= grid.0.distance(&start, &end)
+                    }
+                    _ => false,
+                },
+            }
+        };
+        map.iter()
+            .map(|(cell, _)| *cell)
+            .filter(|cell| inside(cell))
+            .collect()
+    } else {
+        cursor.cell.into_iter().collect()
+    };
+    let changed = selection
+        .bypass_change_detection()
+        .apply(cells, SelectMode::from_keys(&keys));
+    if !changed.is_empty() {
+        selection.set_changed();
+        changes.send(changed);
+    }
+}
+
+/// Whether a point lies within a polygon, by the even-odd rule
+fn encloses(polygon: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    for (a, b) in polygon.iter().zip(polygon.iter().cycle().skip(1)) {
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::SquareCoord,
+        primitives::{GridAlign, GridPrimitive, GridWrap, Squares},
+        testing::spawn_map,
+        GridPlugin,
+    };
+
+    fn app() -> App {
+        let squares = Squares {
+            size: 1.0,
+            alignment: GridAlign::XY,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(GridPlugin::new(squares))
+            .add_plugin(SelectionPlugin::<Squares>::default())
+            .init_resource::<Input<MouseButton>>()
+            .init_resource::<Input<KeyCode>>()
+            .add_startup_system(spawn_map::<Squares>(4, 4));
+        app.update();
+        app
+    }
+
+    /// Moves the cursor through points of the grid plane, in cell widths,
+    /// holding the left button down from the first to the last
+    fn drag(app: &mut App, points: &[(f32, f32)], key: Option<KeyCode>) {
+        let mut keys = app.world.resource_mut::<Input<KeyCode>>();
+        keys.reset_all();
+        if let Some(key) = key {
+            keys.press(key);
+        }
+        let grid = app.world.resource::<GridConfig<Squares>>().0;
+        for (i, (x, y)) in points.iter().enumerate() {
+            let world = Vec3::new(*x, *y, 0.0) * grid.width();
+            *app.world.resource_mut::<GridCursor<SquareCoord>>() = GridCursor {
+                world: Some(world),
+                cell: Some(grid.cell_at(world)),
+            };
+            let mut buttons = app.world.resource_mut::<Input<MouseButton>>();
+            buttons.clear();
+            if i == 0 {
+                buttons.press(MouseButton::Left);
+            }
+            if i == points.len() - 1 {
+                buttons.release(MouseButton::Left);
+            }
+            app.update();
+        }
+    }
+
+    fn selected(app: &App) -> Vec<(i32, i32)> {
+        let mut cells: Vec<_> = app
+            .world
+            .resource::<Selection<SquareCoord>>()
+            .iter()
+            .map(|c| (c.q, c.r))
+            .collect();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn clicks_select_cells() {
+        let mut app = app();
+        drag(&mut app, &[(1.0, 1.0)], None);
+        assert_eq!(selected(&app), [(1, 1)]);
+        drag(&mut app, &[(2.0, 2.0)], Some(KeyCode::LShift));
+        assert_eq!(selected(&app), [(1, 1), (2, 2)]);
+        drag(&mut app, &[(1.0, 1.0)], Some(KeyCode::LControl));
+        assert_eq!(selected(&app), [(2, 2)]);
+        // moving within a cell is still a click
+        drag(&mut app, &[(0.1, 3.0), (-0.2, 2.9)], None);
+        assert_eq!(selected(&app), [(0, 3)]);
+
+        let events = app
+            .world
+            .resource::<Events<SelectionChanged<SquareCoord>>>();
+        let changes: Vec<_> = events.get_reader().iter(events).cloned().collect();
+        assert_eq!(
+            changes.last(),
+            Some(&SelectionChanged {
+                added: vec![SquareCoord { q: 0, r: 3 }],
+                removed: vec![SquareCoord { q: 2, r: 2 }],
+            })
+        );
+    }
+
+    #[test]
+    fn drags_select_areas() {
+        let mut app = app();
+        drag(&mut app, &[(0.0, 0.0), (1.0, 1.0), (2.0, 1.0)], None);
+        assert_eq!(
+            selected(&app),
+            [(0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (2, 1)]
+        );
+        drag(&mut app, &[(3.0, 3.0), (3.0, 2.0)], Some(KeyCode::LShift));
+        assert_eq!(selected(&app).len(), 8);
+
+        *app.world.resource_mut::<DragShape>() = DragShape::Lasso;
+        // an L around the bottom row and the left column
+        let lasso = [
+            (-0.4, -0.4),
+            (2.4, -0.4),
+            (2.4, 0.6),
+            (0.6, 0.6),
+            (0.6, 2.4),
+            (-0.4, 2.4),
+        ];
+        drag(&mut app, &lasso, None);
+        assert_eq!(selected(&app), [(0, 0), (0, 1), (0, 2), (1, 0), (2, 0)]);
+
+        *app.world.resource_mut::<DragShape>() = DragShape::Cells;
+        // a diamond two steps across from its centre
+        drag(&mut app, &[(1.0, 1.0), (2.0, 2.0)], None);
+        assert_eq!(
+            selected(&app),
+            [
+                (0, 0),
+                (0, 1),
+                (0, 2),
+                (1, 0),
+                (1, 1),
+                (1, 2),
+                (1, 3),
+                (2, 0),
+                (2, 1),
+                (2, 2),
+                (3, 1)
+            ]
+        );
+    }
+}
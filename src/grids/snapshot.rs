@@ -0,0 +1,278 @@
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{error::Error, fmt, fs, hash::Hash, io, path::Path};
+
+use super::{
+    coordinates::Coords, layers::GridLayer, primitives::GridPrimitive, GridConfig, GridPosition,
+};
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Ron(ron::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "could not access the snapshot file: {e}"),
+            SnapshotError::Ron(e) => write!(f, "invalid RON snapshot: {e}"),
+            SnapshotError::Json(e) => write!(f, "invalid JSON snapshot: {e}"),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<ron::Error> for SnapshotError {
+    fn from(e: ron::Error) -> Self {
+        SnapshotError::Ron(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for SnapshotError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        SnapshotError::Ron(e.code)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Json(e)
+    }
+}
+
+/// Data layers saved along a grid: a single `GridLayer` resource, or a tuple of them
+pub trait LayerSet: Sized {
+    fn capture(world: &World) -> Self;
+    fn restore(self, world: &mut World);
+}
+
+impl LayerSet for () {
+    fn capture(_world: &World) -> Self {}
+
+    fn restore(self, _world: &mut World) {}
+}
+
+impl<K, V> LayerSet for GridLayer<K, V>
+where
+    K: Coords + Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn capture(world: &World) -> Self {
+        world.get_resource::<Self>().cloned().unwrap_or_default()
+    }
+
+    fn restore(self, world: &mut World) {
+        world.insert_resource(self);
+    }
+}
+
+macro_rules! impl_layer_set {
+    ($($layer:ident),+) => {
+        #[allow(non_snake_case)]
+        impl<$($layer: LayerSet),+> LayerSet for ($($layer,)+) {
+            fn capture(world: &World) -> Self {
+                ($($layer::capture(world),)+)
+            }
+
+            fn restore(self, world: &mut World) {
+                let ($($layer,)+) = self;
+                $($layer.restore(world);)+
+            }
+        }
+    };
+}
+
+impl_layer_set!(A);
+impl_layer_set!(A, B);
+impl_layer_set!(A, B, C);
+impl_layer_set!(A, B, C, D);
+impl_layer_set!(A, B, C, D, E);
+impl_layer_set!(A, B, C, D, E, F);
+
+/// Entity standing on the grid, identified by its `Name` if it has one
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EntitySnapshot<K> {
+    pub name: Option<String>,
+    pub position: K,
+}
+
+/// Everything needed to rebuild a grid: its configuration, the data layers
+/// `L` and the entities with a `GridPosition<K>`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GridSnapshot<T, K, L> {
+    pub config: T,
+    pub layers: L,
+    pub entities: Vec<EntitySnapshot<K>>,
+}
+
+impl<T, K, L> GridSnapshot<T, K, L>
+where
+    T: GridPrimitive + Clone + Send + Sync + 'static,
+    K: Coords + Copy + Send + Sync + 'static,
+    L: LayerSet,
+{
+    pub fn capture(world: &mut World) -> Self {
+        let entities = world
+            .query::<(Option<&Name>, &GridPosition<K>)>()
+            .iter(world)
+            .map(|(name, position)| EntitySnapshot {
+                name: name.map(|n| n.to_string()),
+                position: position.0,
+            })
+            .collect();
+        GridSnapshot {
+            config: world.resource::<GridConfig<T>>().0.clone(),
+            layers: L::capture(world),
+            entities,
+        }
+    }
+
+    /// Replaces the grid configuration and layers, and respawns the entities
+    /// on the grid after despawning the current ones
+    pub fn restore(self, world: &mut World) {
+        let current: Vec<Entity> = world
+            .query_filtered::<Entity, With<GridPosition<K>>>()
+            .iter(world)
+            .collect();
+        for entity in current {
+            world.despawn(entity);
+        }
+        world.insert_resource(GridConfig(self.config));
+        self.layers.restore(world);
+        for EntitySnapshot { name, position } in self.entities {
+            let mut entity = world.spawn(GridPosition(position));
+            if let Some(name) = name {
+                entity.insert(Name::new(name));
+            }
+        }
+    }
+}
+
+impl<T, K, L> GridSnapshot<T, K, L>
+where
+    Self: Serialize + DeserializeOwned,
+{
+    pub fn to_ron(&self) -> Result<String, SnapshotError> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, SnapshotError> {
+        Ok(ron::from_str(text)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, SnapshotError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, SnapshotError> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    /// Writes the snapshot as RON or JSON, depending on the file extension
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let text = match extension(path.as_ref())? {
+            Format::Ron => self.to_ron()?,
+            Format::Json => self.to_json()?,
+        };
+        Ok(fs::write(path, text)?)
+    }
+
+    /// Reads a RON or JSON snapshot, depending on the file extension
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let format = extension(path.as_ref())?;
+        let text = fs::read_to_string(path)?;
+        match format {
+            Format::Ron => Self::from_ron(&text),
+            Format::Json => Self::from_json(&text),
+        }
+    }
+}
+
+enum Format {
+    Ron,
+    Json,
+}
+
+fn extension(path: &Path) -> Result<Format, SnapshotError> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("ron") => Ok(Format::Ron),
+        Some("json") => Ok(Format::Json),
+        _ => Err(SnapshotError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is neither a .ron nor a .json file", path.display()),
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::{HexCoord, HexOrientation},
+        primitives::{GridAlign, GridWrap, Hexes},
+    };
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Terrain {
+        Grass,
+        Water,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Height(f32);
+
+    type Snapshot =
+        GridSnapshot<Hexes, HexCoord, (GridLayer<HexCoord, Terrain>, GridLayer<HexCoord, Height>)>;
+
+    #[test]
+    fn snapshots_round_trip() {
+        let mut world = World::new();
+        world.insert_resource(GridConfig(Hexes {
+            size: 2.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::PointyUp,
+            layer: 0.0,
+            wrap: GridWrap {
+                width: Some(8),
+                height: None,
+            },
+        }));
+        let lake = HexCoord { q: 1, r: -1 };
+        world.insert_resource(
+            [(HexCoord::ZERO, Terrain::Grass), (lake, Terrain::Water)]
+                .into_iter()
+                .collect::<GridLayer<_, _>>(),
+        );
+        world.spawn((GridPosition(lake), Name::new("boat")));
+
+        let snapshot = Snapshot::capture(&mut world);
+        let ron = Snapshot::from_ron(&snapshot.to_ron().unwrap()).unwrap();
+        let json = Snapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+        assert_eq!(ron.entities, snapshot.entities);
+        assert_eq!(json.config.wrap.width, Some(8));
+
+        let mut restored = World::new();
+        json.restore(&mut restored);
+        let terrain = restored.resource::<GridLayer<HexCoord, Terrain>>();
+        assert_eq!(terrain.get(&lake), Some(&Terrain::Water));
+        assert!(restored
+            .resource::<GridLayer<HexCoord, Height>>()
+            .is_empty());
+        let (name, position) = restored
+            .query::<(&Name, &GridPosition<HexCoord>)>()
+            .single(&restored);
+        assert_eq!((name.as_str(), position.0), ("boat", lake));
+    }
+}
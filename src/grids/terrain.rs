@@ -0,0 +1,455 @@
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use std::{error::Error, fmt, hash::Hash, marker::PhantomData};
+
+use super::{
+    coordinates::Coords, layers::GridLayer, BaseColor, CellColor, GridConfig, GridLayout, GridMap,
+};
+
+/// Kind of ground a cell is made of
+#[derive(Clone, Debug)]
+pub struct Terrain {
+    pub name: String,
+    pub color: Color,
+    pub texture: Option<Handle<Image>>,
+    /// Price of entering the cell, at least 1
+    pub cost: u32,
+    pub passable: bool,
+    pub blocks_vision: bool,
+    /// Height of the cell above the grid plane
+    pub elevation: f32,
+}
+
+impl Terrain {
+    /// Flat open ground of the given colour, entered for 1
+    pub fn new(name: impl Into<String>, color: Color) -> Self {
+        Terrain {
+            name: name.into(),
+            color,
+            texture: None,
+            cost: 1,
+            passable: true,
+            blocks_vision: false,
+            elevation: 0.0,
+        }
+    }
+}
+
+/// Index of a terrain in the `TerrainRegistry`, the value of terrain layers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TerrainId(pub u16);
+
+/// Name of a terrain missing from the `TerrainRegistry`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownTerrain(pub String);
+
+impl fmt::Display for UnknownTerrain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown terrain {:?}", self.0)
+    }
+}
+
+impl Error for UnknownTerrain {}
+
+/// Every terrain of the game, by id and name
+#[derive(Resource, Clone, Debug, Default)]
+pub struct TerrainRegistry {
+    terrains: Vec<Terrain>,
+    names: HashMap<String, TerrainId>,
+}
+
+impl TerrainRegistry {
+    /// Adds a terrain, or replaces the one of the same name keeping its id
+    pub fn register(&mut self, terrain: Terrain) -> TerrainId {
+        if let Some(id) = self.names.get(&terrain.name) {
+            self.terrains[id.0 as usize] = terrain;
+            return *id;
+        }
+        let id = TerrainId(self.terrains.len() as u16);
+        self.names.insert(terrain.name.clone(), id);
+        self.terrains.push(terrain);
+        id
+    }
+
+    pub fn get(&self, id: TerrainId) -> Option<&Terrain> {
+        self.terrains.get(id.0 as usize)
+    }
+
+    pub fn id(&self, name: &str) -> Option<TerrainId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TerrainId, &Terrain)> {
+        (0..).map(TerrainId).zip(self.terrains.iter())
+    }
+
+    /// Terrain layer out of a layer of terrain names, such as those of a `LevelMap`
+    pub fn layer<K: Coords + Copy + Eq + Hash>(
+        &self,
+        names: &GridLayer<K, String>,
+    ) -> Result<GridLayer<K, TerrainId>, UnknownTerrain> {
+        names
+            .iter()
+            .map(|(cell, name)| {
+                let id = self.id(name).ok_or_else(|| UnknownTerrain(name.clone()))?;
+                Ok((*cell, id))
+            })
+            .collect()
+    }
+}
+
+impl FromIterator<Terrain> for TerrainRegistry {
+    fn from_iter<I: IntoIterator<Item = Terrain>>(terrains: I) -> Self {
+        let mut registry = TerrainRegistry::default();
+        for terrain in terrains {
+            registry.register(terrain);
+        }
+        registry
+    }
+}
+
+/// Terrain of the cells, for systems to read costs and sight from. Cells
+/// without terrain are open ground entered for 1.
+#[derive(SystemParam)]
+pub struct Terrains<'w, K: Coords + Eq + Hash + Send + Sync + 'static> {
+    registry: Option<Res<'w, TerrainRegistry>>,
+    layer: Option<Res<'w, GridLayer<K, TerrainId>>>,
+}
+
+impl<'w, K: Coords + Eq + Hash + Send + Sync + 'static> Terrains<'w, K> {
+    pub fn get(&self, coord: &K) -> Option<&Terrain> {
+        let id = self.layer.as_ref()?.get(coord)?;
+        self.registry.as_ref()?.get(*id)
+    }
+
+    /// Price of entering the cell, `None` where it cannot be entered
+    pub fn cost(&self, coord: &K) -> Option<u32> {
+        match self.get(coord) {
+            Some(terrain) => terrain.passable.then_some(terrain.cost.max(1)),
+            None => Some(1),
+        }
+    }
+
+    pub fn blocks_vision(&self, coord: &K) -> bool {
+        self.get(coord).is_some_and(|t| t.blocks_vision)
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.registry.as_ref().is_some_and(|r| r.is_changed())
+            || self.layer.as_ref().is_some_and(|l| l.is_changed())
+    }
+
+    /// Cells given another terrain, or whose terrain was redefined, since
+    /// `seen` was last brought up to date by this call
+    pub fn changed_cells(&self, seen: &mut HashMap<K, TerrainId>) -> Vec<K>
+    where
+        K: Copy,
+    {
+        let redefined = self.registry.as_ref().is_some_and(|r| r.is_changed());
+        if !redefined && !self.layer.as_ref().is_some_and(|l| l.is_changed()) {
+            return Vec::new();
+        }
+        let Some(layer) = &self.layer else {
+            return seen.drain().map(|(cell, _)| cell).collect();
+        };
+        let mut changed = Vec::new();
+        seen.retain(|cell, _| {
+            let kept = layer.contains(cell);
+            if !kept {
+                changed.push(*cell);
+            }
+            kept
+        });
+        for (cell, id) in layer.iter() {
+            if seen.insert(*cell, *id) != Some(*id) || redefined {
+                changed.push(*cell);
+            }
+        }
+        changed
+    }
+}
+
+/// Cost of entering the cells of the map by their terrain alone, for paths
+/// walked through other units rather than waiting for room, such as those
+/// of crowds and of the `PathHierarchy`
+#[derive(SystemParam)]
+pub struct PathCosts<'w, K: Coords + Copy + Eq + Hash + Send + Sync + 'static> {
+    map: Res<'w, GridMap<K>>,
+    terrains: Terrains<'w, K>,
+}
+
+impl<'w, K: Coords + Copy + Eq + Hash + Send + Sync + 'static> PathCosts<'w, K> {
+    /// Cells of the map
+    pub fn cells(&self) -> impl Iterator<Item = K> + '_ {
+        self.map.iter().map(|(cell, _)| *cell)
+    }
+
+    pub fn cost(&self, coord: &K) -> Option<u32> {
+        self.map.get(coord)?;
+        self.terrains.cost(coord)
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.map.is_changed() || self.terrains.is_changed()
+    }
+
+    /// Cells added to or taken off the map, or whose terrain changed, since
+    /// `seen` was last brought up to date by this call
+    pub fn changed_cells(&self, seen: &mut SeenCells<K>) -> Vec<K> {
+        let mut changed = Vec::new();
+        if self.map.is_changed() {
+            seen.cells.retain(|cell| {
+                let kept = self.map.get(cell).is_some();
+                if !kept {
+                    changed.push(*cell);
+                }
+                kept
+            });
+            for cell in self.cells() {
+                if seen.cells.insert(cell) {
+                    changed.push(cell);
+                }
+            }
+        }
+        let retyped = self.terrains.changed_cells(&mut seen.terrain);
+        changed.extend(retyped.into_iter().filter(|c| self.map.get(c).is_some()));
+        changed
+    }
+}
+
+/// Cells of the map and their terrain, as last seen by
+/// `PathCosts::changed_cells`
+pub struct SeenCells<K> {
+    cells: HashSet<K>,
+    terrain: HashMap<K, TerrainId>,
+}
+
+impl<K> Default for SeenCells<K> {
+    fn default() -> Self {
+        SeenCells {
+            cells: HashSet::new(),
+            terrain: HashMap::new(),
+        }
+    }
+}
+
+/// Image a cell is drawn with, under its `CellColor`
+#[derive(Component, Clone, Debug)]
+pub struct CellTexture(pub Handle<Image>);
+
+/// Terrain layer of the grid of `T` over the `terrains`, shaping the look,
+/// the height, the movement costs and the sight of its cells
+pub struct TerrainPlugin<T> {
+    terrains: Vec<Terrain>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> TerrainPlugin<T> {
+    pub fn new(terrains: impl IntoIterator<Item = Terrain>) -> Self {
+        TerrainPlugin {
+            terrains: terrains.into_iter().collect(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: GridLayout> Plugin for TerrainPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.terrains.iter().cloned().collect::<TerrainRegistry>())
+            .insert_resource(GridLayer::<T::Coord, TerrainId>::new())
+            .add_system(shape_cells::<T>.run_if(resource_exists::<GridConfig<T>>()));
+    }
+}
+
+/// Gives cell entities the colour, texture and height of their terrain. Cells
+/// without terrain are laid flat, untextured and in their `BaseColor`, taken
+/// from their colour when first shaped if they were spawned without one.
+pub fn shape_cells<T: GridLayout>(
+    mut commands: Commands,
+    grid: Res<GridConfig<T>>,
+    map: Res<GridMap<T::Coord>>,
+    terrains: Terrains<T::Coord>,
+    mut cells: Query<(
+        &mut CellColor,
+        &mut Transform,
+        Option<&BaseColor>,
+        Option<&CellTexture>,
+    )>,
+) {
+    if !terrains.is_changed() && !map.is_changed() && !grid.is_changed() {
+        return;
+    }
+    let up = grid.0.alignment().normal();
+    for (coord, entity) in map.iter() {
+        let Ok((mut color, mut transform, base, texture)) = cells.get_mut(*entity) else {
+            continue;
+        };
+        let base = match base {
+            Some(base) => base.0,
+            None => {
+                commands.entity(*entity).insert(BaseColor(color.0));
+                color.0
+            }
+        };
+        let terrain = terrains.get(coord);
+        let translation =
+            grid.0.cell_transform(coord).translation + up * terrain.map_or(0.0, |t| t.elevation);
+        if transform.translation != translation {
+            transform.translation = translation;
+        }
+        let painted = terrain.map_or(base, |t| t.color);
+        if color.0 != painted {
+            color.0 = painted;
+        }
+        match (terrain.and_then(|t| t.texture.as_ref()), texture) {
+            (Some(image), Some(CellTexture(current))) if image == current => {}
+            (Some(image), _) => {
+                commands.entity(*entity).insert(CellTexture(image.clone()));
+            }
+            (None, Some(_)) => {
+                commands.entity(*entity).remove::<CellTexture>();
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::{HexCoord, HexOrientation},
+        fog::{Faction, FogOfWar, FogOfWarPlugin, FogState, Vision},
+        pathfinding::find_path,
+        primitives::{GridAlign, GridWrap, Hexes},
+        testing::spawn_map,
+        GridPlugin, GridPosition,
+    };
+
+    fn terrains() -> Vec<Terrain> {
+        vec![
+            Terrain::new("grass", Color::GREEN),
+            Terrain {
+                cost: 3,
+                blocks_vision: true,
+                elevation: 0.5,
+                ..Terrain::new("mountain", Color::GRAY)
+            },
+            Terrain {
+                passable: false,
+                ..Terrain::new("lake", Color::BLUE)
+            },
+        ]
+    }
+
+    #[test]
+    fn terrain_layers_from_names() {
+        let registry: TerrainRegistry = terrains().into_iter().collect();
+        let mut names = GridLayer::new();
+        names.insert(HexCoord::ZERO, "lake".to_string());
+        let layer = registry.layer(&names).unwrap();
+        assert_eq!(layer.get(&HexCoord::ZERO), registry.id("lake").as_ref());
+        names.insert(HexCoord { q: 1, r: 0 }, "lava".to_string());
+        assert_eq!(
+            registry.layer(&names).err(),
+            Some(UnknownTerrain("lava".to_string()))
+        );
+    }
+
+    #[test]
+    fn mountains_cost_block_sight_and_rise() {
+        let hexes = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::PointyUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(GridPlugin::new(hexes.clone()))
+            .add_plugin(TerrainPlugin::<Hexes>::new(terrains()))
+            .add_plugin(FogOfWarPlugin::<HexCoord>::default())
+            .add_startup_system(spawn_map::<Hexes>(6, 1));
+        let red = Faction(0);
+        app.world
+            .spawn((GridPosition(HexCoord::ZERO), Vision { radius: 5 }, red));
+        app.update();
+        let far = HexCoord { q: 3, r: 0 };
+        let fog = app.world.resource::<FogOfWar<HexCoord>>();
+        assert_eq!(fog.state(&red, &far), FogState::Visible);
+
+        let registry = app.world.resource::<TerrainRegistry>();
+        let mountain = registry.id("mountain").unwrap();
+        let peak = HexCoord { q: 2, r: 0 };
+        app.world
+            .resource_mut::<GridLayer<HexCoord, TerrainId>>()
+            .insert(peak, mountain);
+        app.update();
+
+        let fog = app.world.resource::<FogOfWar<HexCoord>>();
+        assert_eq!(fog.state(&red, &peak), FogState::Visible);
+        assert_eq!(fog.state(&red, &far), FogState::Explored);
+        let cell = app
+            .world
+            .resource::<GridMap<HexCoord>>()
+            .get(&peak)
+            .unwrap();
+        assert_eq!(app.world.get::<CellColor>(cell).unwrap().0, Color::GRAY);
+        assert_eq!(app.world.get::<Transform>(cell).unwrap().translation.y, 0.5);
+
+        let mut state: bevy::ecs::system::SystemState<Terrains<HexCoord>> =
+            bevy::ecs::system::SystemState::new(&mut app.world);
+        let terrains = state.get(&app.world);
+        let path = find_path(&hexes, HexCoord::ZERO, far, |c| terrains.cost(c)).unwrap();
+        // around the mountain rather than over it
+        assert_eq!(path.cost, 4);
+        assert!(!path.cells.contains(&peak));
+    }
+
+    #[test]
+    fn cleared_cells_return_to_their_base() {
+        let hexes = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::PointyUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let mut app = App::new();
+        let painted = Terrain {
+            texture: Some(Handle::default()),
+            elevation: 0.5,
+            ..Terrain::new("rock", Color::GRAY)
+        };
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(GridPlugin::new(hexes))
+            .add_plugin(TerrainPlugin::<Hexes>::new([painted]))
+            .add_startup_system(spawn_map::<Hexes>(2, 1));
+        app.update();
+        let rock = app.world.resource::<TerrainRegistry>().id("rock").unwrap();
+        let cell = app
+            .world
+            .resource::<GridMap<HexCoord>>()
+            .get(&HexCoord::ZERO)
+            .unwrap();
+        let flat = app.world.get::<Transform>(cell).unwrap().translation;
+        app.world
+            .resource_mut::<GridLayer<HexCoord, TerrainId>>()
+            .insert(HexCoord::ZERO, rock);
+        app.update();
+        assert_eq!(app.world.get::<CellColor>(cell).unwrap().0, Color::GRAY);
+        assert!(app.world.get::<CellTexture>(cell).is_some());
+
+        app.world
+            .resource_mut::<GridLayer<HexCoord, TerrainId>>()
+            .remove(&HexCoord::ZERO);
+        app.update();
+        assert_eq!(app.world.get::<CellColor>(cell).unwrap().0, Color::GREEN);
+        assert!(app.world.get::<CellTexture>(cell).is_none());
+        assert_eq!(app.world.get::<Transform>(cell).unwrap().translation, flat);
+    }
+}
@@ -0,0 +1,29 @@
+//! Helpers shared by the tests of the grid modules
+
+use bevy::{prelude::*, utils::Duration};
+
+use super::{coordinates::SquareCoord, spawn_grid, GridConfig, GridLayout, GridMap};
+
+pub fn square(q: i32, r: i32) -> SquareCoord {
+    SquareCoord { q, r }
+}
+
+/// Moves the clock of an app inserting its own `Time` on by `seconds`, then
+/// runs its systems once
+pub fn tick(app: &mut App, seconds: f32) {
+    let mut time = app.world.resource_mut::<Time>();
+    let now = time.last_update().unwrap_or(time.startup());
+    time.update_with_instant(now + Duration::from_secs_f32(seconds));
+    app.update();
+}
+
+/// Startup system spawning a `width` by `height` map of the grid of `T`
+#[allow(clippy::type_complexity)]
+pub fn spawn_map<T: GridLayout>(
+    width: u32,
+    height: u32,
+) -> impl FnMut(Commands, Res<GridConfig<T>>, ResMut<GridMap<T::Coord>>) {
+    move |mut commands, grid, mut map| {
+        spawn_grid(&mut commands, &grid, (width, height), &mut map);
+    }
+}
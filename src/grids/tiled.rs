@@ -0,0 +1,697 @@
+use base64::Engine;
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+use std::{collections::BTreeMap, error::Error, fmt, fs, hash::Hash, io, path::Path};
+
+use super::{
+    coordinates::{Coords, HexCoord, HexOrientation, SquareCoord},
+    layers::GridLayer,
+    primitives::{GridAlign, GridWrap, Hexes},
+    GridPosition,
+};
+
+/// Bits of a tile id flagging flipped and rotated tiles
+const GID_MASK: u32 = 0x0FFF_FFFF;
+
+#[derive(Debug)]
+pub enum TiledError {
+    Io(io::Error),
+    Xml(roxmltree::Error),
+    Json(serde_json::Error),
+    Base64(base64::DecodeError),
+    Invalid(String),
+    Unsupported(String),
+    WrongOrientation(TiledOrientation),
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TiledError::Io(e) => write!(f, "could not access the Tiled map: {e}"),
+            TiledError::Xml(e) => write!(f, "invalid TMX map: {e}"),
+            TiledError::Json(e) => write!(f, "invalid JSON map: {e}"),
+            TiledError::Base64(e) => write!(f, "invalid tile layer data: {e}"),
+            TiledError::Invalid(e) => write!(f, "invalid Tiled map: {e}"),
+            TiledError::Unsupported(e) => write!(f, "unsupported Tiled map: {e}"),
+            TiledError::WrongOrientation(o) => {
+                write!(f, "{o:?} maps cannot be imported onto these cells")
+            }
+        }
+    }
+}
+
+impl Error for TiledError {}
+
+impl From<io::Error> for TiledError {
+    fn from(e: io::Error) -> Self {
+        TiledError::Io(e)
+    }
+}
+
+impl From<roxmltree::Error> for TiledError {
+    fn from(e: roxmltree::Error) -> Self {
+        TiledError::Xml(e)
+    }
+}
+
+impl From<serde_json::Error> for TiledError {
+    fn from(e: serde_json::Error) -> Self {
+        TiledError::Json(e)
+    }
+}
+
+impl From<base64::DecodeError> for TiledError {
+    fn from(e: base64::DecodeError) -> Self {
+        TiledError::Base64(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StaggerAxis {
+    /// Staggered columns, of flat hexes
+    X,
+    /// Staggered rows, of pointy hexes
+    Y,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StaggerIndex {
+    Odd,
+    Even,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TiledOrientation {
+    Orthogonal,
+    Hexagonal {
+        stagger_axis: StaggerAxis,
+        stagger_index: StaggerIndex,
+        side_length: f32,
+    },
+}
+
+/// Object of a Tiled object layer, spawned along a `GridPosition`
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub struct TiledObject {
+    pub layer: String,
+    pub name: String,
+    pub class: String,
+    pub properties: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum TiledLayer {
+    /// Tile ids row by row, 0 for empty tiles
+    Tiles { name: String, tiles: Vec<u32> },
+    /// Objects along the pixel position of their centre
+    Objects {
+        name: String,
+        objects: Vec<(Vec2, TiledObject)>,
+    },
+}
+
+/// Map authored in Tiled, read from a TMX or JSON file
+#[derive(Clone, Debug)]
+pub struct TiledMap {
+    pub orientation: TiledOrientation,
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: f32,
+    pub tile_height: f32,
+    pub layers: Vec<TiledLayer>,
+}
+
+/// Layers of a Tiled map put onto cells `K`: tile ids by layer name, and the objects
+pub struct TiledImport<K: Coords> {
+    pub layers: BTreeMap<String, GridLayer<K, u32>>,
+    pub objects: Vec<(K, TiledObject)>,
+}
+
+impl<K: Coords + Copy + Send + Sync + 'static> TiledImport<K> {
+    /// Spawns an entity per object, named after it
+    pub fn spawn_objects(&self, commands: &mut Commands) -> Vec<Entity> {
+        self.objects
+            .iter()
+            .map(|(cell, object)| {
+                commands
+                    .spawn((
+                        Name::new(object.name.clone()),
+                        GridPosition(*cell),
+                        object.clone(),
+                    ))
+                    .id()
+            })
+            .collect()
+    }
+}
+
+/// Cells a Tiled map can be imported onto: squares for orthogonal maps, hexes
+/// for staggered hexagonal ones
+pub trait TiledCoord: Coords + Copy + Eq + Hash {
+    /// Cell of the tile in column `col` and row `row`, counting rows down
+    fn at_tile(map: &TiledMap, col: u32, row: u32) -> Option<Self>;
+
+    /// Cell under a pixel of the map
+    fn at_pixel(map: &TiledMap, pos: Vec2) -> Option<Self>;
+}
+
+/// Rows count up from the bottom of the map, North being +r
+impl TiledCoord for SquareCoord {
+    fn at_tile(map: &TiledMap, col: u32, row: u32) -> Option<Self> {
+        (map.orientation == TiledOrientation::Orthogonal).then(|| SquareCoord {
+            q: col as i32,
+            r: map.height as i32 - 1 - row as i32,
+        })
+    }
+
+    fn at_pixel(map: &TiledMap, pos: Vec2) -> Option<Self> {
+        let col = (pos.x / map.tile_width).floor() as i32;
+        let row = (pos.y / map.tile_height).floor() as i32;
+        (map.orientation == TiledOrientation::Orthogonal).then(|| SquareCoord {
+            q: col,
+            r: map.height as i32 - 1 - row,
+        })
+    }
+}
+
+/// Tiles are laid out as by `Hexes::at_offset`, rows going south. Maps
+/// staggering their even rows or columns start one row or column in, so
+/// that every tile keeps the side Tiled shifted it to.
+impl TiledCoord for HexCoord {
+    fn at_tile(map: &TiledMap, col: u32, row: u32) -> Option<Self> {
+        let hexes = map.hexes()?;
+        let (col, row) = (col as i32, row as i32);
+        let TiledOrientation::Hexagonal {
+            stagger_axis,
+            stagger_index,
+            ..
+        } = map.orientation
+        else {
+            return None;
+        };
+        let shift = (stagger_index == StaggerIndex::Even) as i32;
+        Some(match stagger_axis {
+            StaggerAxis::Y => hexes.at_offset(col, row + shift),
+            StaggerAxis::X => hexes.at_offset(col + shift, row),
+        })
+    }
+
+    fn at_pixel(map: &TiledMap, pos: Vec2) -> Option<Self> {
+        let hexes = map.hexes()?;
+        let TiledOrientation::Hexagonal {
+            stagger_axis,
+            stagger_index,
+            side_length,
+        } = map.orientation
+        else {
+            return None;
+        };
+        let (width, height) = (map.tile_width, map.tile_height);
+        let shift = (stagger_index == StaggerIndex::Even) as i32 as f32;
+        // Relative to the centre of the first tile, in hexes of size 1
+        let (x, y) = (pos.x - width / 2.0, pos.y - height / 2.0);
+        let pos = match stagger_axis {
+            StaggerAxis::Y => {
+                let advance = (height + side_length) / 2.0;
+                Vec3::new(x / width * 3_f32.sqrt(), 0.0, (y / advance + shift) * 1.5)
+            }
+            StaggerAxis::X => {
+                let advance = (width + side_length) / 2.0;
+                Vec3::new((x / advance + shift) * 1.5, 0.0, y / height * 3_f32.sqrt())
+            }
+        };
+        Some(HexCoord::new_from_world_pos(pos, &hexes))
+    }
+}
+
+impl TiledMap {
+    /// Reads a TMX file, or a JSON one for `.json` and `.tmj` extensions
+    pub fn load(path: impl AsRef<Path>) -> Result<TiledMap, TiledError> {
+        let text = fs::read_to_string(path.as_ref())?;
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("json" | "tmj") => TiledMap::from_json(&text),
+            _ => TiledMap::from_tmx(&text),
+        }
+    }
+
+    /// Orientation of the hexes of a hexagonal map
+    pub fn hex_orientation(&self) -> Option<HexOrientation> {
+        match self.orientation {
+            TiledOrientation::Orthogonal => None,
+            TiledOrientation::Hexagonal {
+                stagger_axis: StaggerAxis::Y,
+                ..
+            } => Some(HexOrientation::PointyUp),
+            TiledOrientation::Hexagonal {
+                stagger_axis: StaggerAxis::X,
+                ..
+            } => Some(HexOrientation::FlatUp),
+        }
+    }
+
+    /// Unit hexes the tiles are converted through
+    fn hexes(&self) -> Option<Hexes> {
+        Some(Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: self.hex_orientation()?,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        })
+    }
+
+    /// Fails on tile layers without exactly one tile per cell of the map
+    fn check_layers(&self) -> Result<(), TiledError> {
+        let cells = self.width as usize * self.height as usize;
+        for layer in &self.layers {
+            match layer {
+                TiledLayer::Tiles { name, tiles } if tiles.len() != cells => {
+                    return Err(TiledError::Invalid(format!(
+                        "layer {name} has {} tiles for a {}x{} map",
+                        tiles.len(),
+                        self.width,
+                        self.height
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts tile layers into data layers and objects onto the cell under them
+    pub fn import<K: TiledCoord>(&self) -> Result<TiledImport<K>, TiledError> {
+        if K::at_tile(self, 0, 0).is_none() {
+            return Err(TiledError::WrongOrientation(self.orientation));
+        }
+        self.check_layers()?;
+        let mut layers = BTreeMap::new();
+        let mut objects = Vec::new();
+        for layer in &self.layers {
+            match layer {
+                TiledLayer::Tiles { name, tiles } => {
+                    let cells = (0..self.height)
+                        .flat_map(|row| (0..self.width).map(move |col| (col, row)))
+                        .filter_map(|(col, row)| K::at_tile(self, col, row));
+                    let layer = cells
+                        .zip(tiles)
+                        .filter(|(_, &tile)| tile != 0)
+                        .map(|(cell, &tile)| (cell, tile & GID_MASK))
+                        .collect();
+                    layers.insert(name.clone(), layer);
+                }
+                TiledLayer::Objects { objects: all, .. } => {
+                    objects.extend(all.iter().filter_map(|(pos, object)| {
+                        Some((K::at_pixel(self, *pos)?, object.clone()))
+                    }));
+                }
+            }
+        }
+        Ok(TiledImport { layers, objects })
+    }
+
+    pub fn from_tmx(text: &str) -> Result<TiledMap, TiledError> {
+        let document = roxmltree::Document::parse(text)?;
+        let root = document.root_element();
+        let attribute = |name| {
+            root.attribute(name)
+                .ok_or_else(|| TiledError::Invalid(format!("the map has no {name}")))
+        };
+        if root.attribute("infinite") == Some("1") {
+            return Err(TiledError::Unsupported("infinite maps".to_string()));
+        }
+        let mut map = TiledMap {
+            orientation: orientation(
+                attribute("orientation")?,
+                root.attribute("staggeraxis"),
+                root.attribute("staggerindex"),
+                number(root.attribute("hexsidelength").unwrap_or("0"))?,
+            )?,
+            width: number(attribute("width")?)?,
+            height: number(attribute("height")?)?,
+            tile_width: number(attribute("tilewidth")?)?,
+            tile_height: number(attribute("tileheight")?)?,
+            layers: Vec::new(),
+        };
+        tmx_layers(root, &mut map.layers)?;
+        map.check_layers()?;
+        Ok(map)
+    }
+
+    pub fn from_json(text: &str) -> Result<TiledMap, TiledError> {
+        let json: JsonMap = serde_json::from_str(text)?;
+        if json.infinite {
+            return Err(TiledError::Unsupported("infinite maps".to_string()));
+        }
+        let mut layers = Vec::new();
+        json_layers(json.layers, &mut layers)?;
+        let map = TiledMap {
+            orientation: orientation(
+                &json.orientation,
+                json.staggeraxis.as_deref(),
+                json.staggerindex.as_deref(),
+                json.hexsidelength,
+            )?,
+            width: json.width,
+            height: json.height,
+            tile_width: json.tilewidth,
+            tile_height: json.tileheight,
+            layers,
+        };
+        map.check_layers()?;
+        Ok(map)
+    }
+}
+
+fn orientation(
+    kind: &str,
+    axis: Option<&str>,
+    index: Option<&str>,
+    side_length: f32,
+) -> Result<TiledOrientation, TiledError> {
+    match kind {
+        "orthogonal" => Ok(TiledOrientation::Orthogonal),
+        "hexagonal" => Ok(TiledOrientation::Hexagonal {
+            stagger_axis: match axis {
+                Some("x") => StaggerAxis::X,
+                _ => StaggerAxis::Y,
+            },
+            stagger_index: match index {
+                Some("even") => StaggerIndex::Even,
+                _ => StaggerIndex::Odd,
+            },
+            side_length,
+        }),
+        other => Err(TiledError::Unsupported(format!("{other} maps"))),
+    }
+}
+
+fn number<N: std::str::FromStr>(text: &str) -> Result<N, TiledError> {
+    text.trim()
+        .parse()
+        .map_err(|_| TiledError::Invalid(format!("{text:?} is not a number")))
+}
+
+/// Tile ids of a layer's data, given its encoding and compression
+fn tile_data(
+    encoding: Option<&str>,
+    compression: Option<&str>,
+    data: &str,
+) -> Result<Vec<u32>, TiledError> {
+    if let Some(compression) = compression.filter(|c| !c.is_empty()) {
+        return Err(TiledError::Unsupported(format!(
+            "{compression} compression"
+        )));
+    }
+    match encoding {
+        Some("csv") => data.split(',').map(number).collect(),
+        Some("base64") => Ok(base64::engine::general_purpose::STANDARD
+            .decode(data.trim())?
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()),
+        other => Err(TiledError::Unsupported(format!("{other:?} encoding"))),
+    }
+}
+
+fn tmx_layers(parent: roxmltree::Node, layers: &mut Vec<TiledLayer>) -> Result<(), TiledError> {
+    for node in parent.children().filter(|n| n.is_element()) {
+        let name = node.attribute("name").unwrap_or_default().to_string();
+        match node.tag_name().name() {
+            "layer" => {
+                let Some(data) = node.children().find(|n| n.has_tag_name("data")) else {
+                    continue;
+                };
+                let tiles = match data.attribute("encoding") {
+                    None => data
+                        .children()
+                        .filter(|n| n.has_tag_name("tile"))
+                        .map(|t| number(t.attribute("gid").unwrap_or("0")))
+                        .collect::<Result<_, _>>()?,
+                    encoding => tile_data(
+                        encoding,
+                        data.attribute("compression"),
+                        data.text().unwrap_or_default(),
+                    )?,
+                };
+                layers.push(TiledLayer::Tiles { name, tiles });
+            }
+            "objectgroup" => {
+                let objects = node
+                    .children()
+                    .filter(|n| n.has_tag_name("object"))
+                    .map(|o| tmx_object(o, &name))
+                    .collect::<Result<_, _>>()?;
+                layers.push(TiledLayer::Objects { name, objects });
+            }
+            "group" => tmx_layers(node, layers)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn tmx_object(node: roxmltree::Node, layer: &str) -> Result<(Vec2, TiledObject), TiledError> {
+    let value = |name| number::<f32>(node.attribute(name).unwrap_or("0"));
+    let properties = node
+        .children()
+        .filter(|n| n.has_tag_name("properties"))
+        .flat_map(|p| p.children().filter(|n| n.has_tag_name("property")))
+        .map(|p| {
+            let name = p.attribute("name").unwrap_or_default().to_string();
+            let value = p.attribute("value").or(p.text()).unwrap_or_default();
+            (name, value.to_string())
+        })
+        .collect();
+    let object = TiledObject {
+        layer: layer.to_string(),
+        name: node.attribute("name").unwrap_or_default().to_string(),
+        class: node
+            .attribute("class")
+            .or(node.attribute("type"))
+            .unwrap_or_default()
+            .to_string(),
+        properties,
+    };
+    let pos = object_centre(
+        Vec2::new(value("x")?, value("y")?),
+        Vec2::new(value("width")?, value("height")?),
+        node.has_attribute("gid"),
+    );
+    Ok((pos, object))
+}
+
+/// Centre of an object from its position, the top left corner, or the bottom
+/// left one for tile objects
+fn object_centre(pos: Vec2, size: Vec2, tile: bool) -> Vec2 {
+    if tile {
+        pos + Vec2::new(size.x, -size.y) / 2.0
+    } else {
+        pos + size / 2.0
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonMap {
+    orientation: String,
+    width: u32,
+    height: u32,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    hexsidelength: f32,
+    staggeraxis: Option<String>,
+    staggerindex: Option<String>,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    data: Option<serde_json::Value>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+fn json_layers(json: Vec<JsonLayer>, layers: &mut Vec<TiledLayer>) -> Result<(), TiledError> {
+    for layer in json {
+        match layer.kind.as_str() {
+            "tilelayer" => {
+                let tiles = match layer.data {
+                    Some(serde_json::Value::String(data)) => tile_data(
+                        layer.encoding.as_deref(),
+                        layer.compression.as_deref(),
+                        &data,
+                    )?,
+                    Some(data) => serde_json::from_value(data)?,
+                    None => continue,
+                };
+                layers.push(TiledLayer::Tiles {
+                    name: layer.name,
+                    tiles,
+                });
+            }
+            "objectgroup" => {
+                let objects = layer
+                    .objects
+                    .into_iter()
+                    .map(|o| {
+                        let pos = object_centre(
+                            Vec2::new(o.x, o.y),
+                            Vec2::new(o.width, o.height),
+                            o.gid.is_some(),
+                        );
+                        let properties = o
+                            .properties
+                            .into_iter()
+                            .map(|p| match p.value {
+                                serde_json::Value::String(value) => (p.name, value),
+                                value => (p.name, value.to_string()),
+                            })
+                            .collect();
+                        let object = TiledObject {
+                            layer: layer.name.clone(),
+                            name: o.name,
+                            class: if o.class.is_empty() { o.kind } else { o.class },
+                            properties,
+                        };
+                        (pos, object)
+                    })
+                    .collect();
+                layers.push(TiledLayer::Objects {
+                    name: layer.name,
+                    objects,
+                });
+            }
+            "group" => json_layers(layer.layers, layers)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orthogonal_tmx_maps() {
+        let map = TiledMap::from_tmx(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <map version="1.10" orientation="orthogonal" width="3" height="2"
+                 tilewidth="16" tileheight="16" infinite="0">
+              <layer id="1" name="ground" width="3" height="2">
+                <data encoding="csv">1,2,0,
+            3,2147483652,1</data>
+              </layer>
+              <objectgroup id="2" name="units">
+                <object id="1" name="knight" type="unit" x="20" y="4" width="8" height="8">
+                  <properties><property name="hp" type="int" value="10"/></properties>
+                </object>
+              </objectgroup>
+            </map>"#,
+        )
+        .unwrap();
+        let import = map.import::<SquareCoord>().unwrap();
+        let ground = &import.layers["ground"];
+        assert_eq!(ground.len(), 5);
+        // Top left tile, in the upper row
+        assert_eq!(ground.get(&SquareCoord { q: 0, r: 1 }), Some(&1));
+        // Flipped tile
+        assert_eq!(ground.get(&SquareCoord { q: 1, r: 0 }), Some(&4));
+
+        let (cell, knight) = &import.objects[0];
+        assert_eq!(*cell, SquareCoord { q: 1, r: 1 });
+        assert_eq!(knight.class, "unit");
+        assert_eq!(knight.properties["hp"], "10");
+        assert!(map.import::<HexCoord>().is_err());
+    }
+
+    #[test]
+    fn tile_layers_fill_the_map() {
+        let json = |width, height, data| {
+            format!(
+                r#"{{
+                    "orientation": "orthogonal", "width": {width}, "height": {height},
+                    "tilewidth": 16, "tileheight": 16, "infinite": false,
+                    "layers": [{{ "type": "tilelayer", "name": "walls", "data": {data} }}]
+                }}"#
+            )
+        };
+        let error = TiledMap::from_json(&json(2, 2, "[1, 2, 3]")).unwrap_err();
+        assert!(error.to_string().contains("layer walls has 3 tiles"));
+        let error = TiledMap::from_json(&json(2, 0, "[1, 2]")).unwrap_err();
+        assert!(error.to_string().contains("layer walls"));
+
+        let empty = TiledMap::from_json(&json(3, 0, "[]")).unwrap();
+        let import = empty.import::<SquareCoord>().unwrap();
+        assert!(import.layers["walls"].is_empty());
+    }
+
+    #[test]
+    fn staggered_json_maps() {
+        for (index, first_row_shift) in [("odd", 0), ("even", 1)] {
+            let map = TiledMap::from_json(&format!(
+                r#"{{
+                    "orientation": "hexagonal", "staggeraxis": "y", "staggerindex": "{index}",
+                    "hexsidelength": 8, "width": 2, "height": 2,
+                    "tilewidth": 14, "tileheight": 16, "infinite": false,
+                    "layers": [
+                        {{ "type": "tilelayer", "name": "terrain", "data": [1, 2, 3, 4] }},
+                        {{ "type": "objectgroup", "name": "towns", "objects": [
+                            {{ "name": "port", "class": "town", "x": 26, "y": 20 }}
+                        ] }}
+                    ]
+                }}"#
+            ))
+            .unwrap();
+            assert!(matches!(
+                map.hex_orientation(),
+                Some(HexOrientation::PointyUp)
+            ));
+            let import = map.import::<HexCoord>().unwrap();
+            let terrain = &import.layers["terrain"];
+            let hexes = map.hexes().unwrap();
+            // The second row is shifted right by odd maps, left by even ones
+            for (tile, (col, row)) in [(1, (0, 0)), (2, (1, 0)), (3, (0, 1)), (4, (1, 1))] {
+                let cell = hexes.at_offset(col, row + first_row_shift);
+                assert_eq!(terrain.get(&cell), Some(&tile));
+            }
+            assert_eq!(import.objects[0].0, hexes.at_offset(1, 1 + first_row_shift));
+        }
+    }
+}
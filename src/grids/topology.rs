@@ -0,0 +1,384 @@
+use bevy::prelude::*;
+use std::hash::Hash;
+
+use super::{
+    coordinates::{Coords, HexCoord, HexOrientation, SquareCoord, TriangleCoord},
+    primitives::{GridPrimitive, GridWrap, Hexes, Squares, Triangles, WrapError},
+    GridConfig, GridMap,
+};
+
+/// How the cells of a grid connect, taking its wrapping into account.
+/// Wrapped coordinates are kept within the map by `normalize`, which takes
+/// wrap sizes passing `check_wrap`.
+pub trait Topology<K: Coords + Copy> {
+    fn wrapping(&self) -> GridWrap;
+
+    /// Axes, width then height, wrapping only after an even number of cells
+    fn even_wrap(&self) -> (bool, bool) {
+        (false, false)
+    }
+
+    /// Checks the grid wraps by sizes it can wrap by, which `normalize` and
+    /// everything built on it rely on
+    fn check_wrap(&self) -> Result<(), WrapError> {
+        self.wrapping().validate(self.even_wrap())
+    }
+
+    /// Column and row of the cell within a rectangular map
+    fn offset(&self, coord: &K) -> (i32, i32);
+
+    /// Moves `coord` by `dx` map widths and `dy` map heights
+    fn translate(&self, coord: &K, dx: i32, dy: i32) -> K;
+
+    /// Centre of the cell in world space
+    fn cell_pos(&self, coord: &K) -> Vec3;
+
+    /// Same cell, within the map on the wrapping axes
+    fn normalize(&self, coord: &K) -> K {
+        let (col, row) = self.offset(coord);
+        let wrap = self.wrapping();
+        let dx = wrap.width.map_or(0, |w| -col.div_euclid(w as i32));
+        let dy = wrap.height.map_or(0, |h| -row.div_euclid(h as i32));
+        self.translate(coord, dx, dy)
+    }
+
+    /// Copy of `other` nearest to `coord`, possibly outside of the map
+    fn nearest_image(&self, coord: &K, other: &K) -> K {
+        let wrap = self.wrapping();
+        let xs = if wrap.width.is_some() { -1..=1 } else { 0..=0 };
+        let ys = if wrap.height.is_some() { -1..=1 } else { 0..=0 };
+        let (coord, other) = (self.normalize(coord), self.normalize(other));
+        xs.flat_map(|dx| ys.clone().map(move |dy| (dx, dy)))
+            .map(|(dx, dy)| self.translate(&other, dx, dy))
+            .min_by_key(|image| coord.distance(image))
+            .unwrap()
+    }
+
+    fn neighbours(&self, coord: &K) -> Vec<K> {
+        coord
+            .neighbours()
+            .iter()
+            .map(|n| self.normalize(n))
+            .collect()
+    }
+
+    fn distance(&self, a: &K, b: &K) -> u32 {
+        self.normalize(a).distance(&self.nearest_image(a, b))
+    }
+
+    /// Shortest line from `a` to `b`, crossing the seams if need be
+    fn line(&self, a: &K, b: &K) -> Vec<K> {
+        self.normalize(a)
+            .line_to(&self.nearest_image(a, b))
+            .iter()
+            .map(|c| self.normalize(c))
+            .collect()
+    }
+
+    /// World space offsets between a cell and its copies one map width and
+    /// one map height away, if the grid wraps that way
+    fn world_period(&self) -> (Option<Vec3>, Option<Vec3>) {
+        let wrap = self.wrapping();
+        let origin = self.normalize(&K::ZERO);
+        let period =
+            |dx, dy| self.cell_pos(&self.translate(&origin, dx, dy)) - self.cell_pos(&origin);
+        (
+            wrap.width.map(|_| period(1, 0)),
+            wrap.height.map(|_| period(0, 1)),
+        )
+    }
+}
+
+impl Topology<SquareCoord> for Squares {
+    fn wrapping(&self) -> GridWrap {
+        self.wrap
+    }
+
+    fn offset(&self, coord: &SquareCoord) -> (i32, i32) {
+        (coord.q, coord.r)
+    }
+
+    fn translate(&self, coord: &SquareCoord, dx: i32, dy: i32) -> SquareCoord {
+        SquareCoord {
+            q: coord.q + dx * self.wrap.width.unwrap_or(0) as i32,
+            r: coord.r + dy * self.wrap.height.unwrap_or(0) as i32,
+        }
+    }
+
+    fn cell_pos(&self, coord: &SquareCoord) -> Vec3 {
+        coord.to_vec3(self)
+    }
+}
+
+impl Topology<HexCoord> for Hexes {
+    fn wrapping(&self) -> GridWrap {
+        self.wrap
+    }
+
+    fn even_wrap(&self) -> (bool, bool) {
+        match self.orientation {
+            HexOrientation::PointyUp => (false, true),
+            HexOrientation::FlatUp => (true, false),
+        }
+    }
+
+    fn offset(&self, coord: &HexCoord) -> (i32, i32) {
+        match self.orientation {
+            HexOrientation::PointyUp => (coord.q + coord.r.div_euclid(2), coord.r),
+            HexOrientation::FlatUp => (coord.q, coord.r + coord.q.div_euclid(2)),
+        }
+    }
+
+    fn translate(&self, coord: &HexCoord, dx: i32, dy: i32) -> HexCoord {
+        let (col, row) = self.offset(coord);
+        self.at_offset(
+            col + dx * self.wrap.width.unwrap_or(0) as i32,
+            row + dy * self.wrap.height.unwrap_or(0) as i32,
+        )
+    }
+
+    fn cell_pos(&self, coord: &HexCoord) -> Vec3 {
+        coord.to_vec3(self)
+    }
+}
+
+impl Hexes {
+    /// Cell at an offset column and row, the inverse of `Topology::offset`
+    pub fn at_offset(&self, col: i32, row: i32) -> HexCoord {
+        match self.orientation {
+            HexOrientation::PointyUp => HexCoord {
+                q: col - row.div_euclid(2),
+                r: row,
+            },
+            HexOrientation::FlatUp => HexCoord {
+                q: col,
+                r: row - col.div_euclid(2),
+            },
+        }
+    }
+}
+
+impl Topology<TriangleCoord> for Triangles {
+    fn wrapping(&self) -> GridWrap {
+        self.wrap
+    }
+
+    fn even_wrap(&self) -> (bool, bool) {
+        (false, true)
+    }
+
+    fn offset(&self, coord: &TriangleCoord) -> (i32, i32) {
+        (coord.q, coord.r)
+    }
+
+    fn translate(&self, coord: &TriangleCoord, dx: i32, dy: i32) -> TriangleCoord {
+        TriangleCoord {
+            q: coord.q + dx * self.wrap.width.unwrap_or(0) as i32,
+            r: coord.r + dy * self.wrap.height.unwrap_or(0) as i32,
+            flip: coord.flip,
+        }
+    }
+
+    fn cell_pos(&self, coord: &TriangleCoord) -> Vec3 {
+        coord.to_vec3(self)
+    }
+}
+
+impl<K: Coords + Copy + Eq + Hash> GridMap<K> {
+    /// Entity of the cell, looking up wrapped coordinates within the map
+    pub fn get_wrapped<T: Topology<K>>(&self, coord: &K, grid: &T) -> Option<Entity> {
+        self.get(&grid.normalize(coord))
+    }
+}
+
+/// Columns and rows of cells copied across the seams of a wrapping grid
+#[cfg(feature = "render")]
+const SEAM_MARGIN: i32 = 4;
+
+/// Copy of a cell entity drawn on the other side of a seam
+#[derive(Component)]
+pub struct SeamCopy(pub Entity);
+
+/// Moved back into the map when crossing a seam, e.g. a camera panning across
+#[derive(Component)]
+pub struct WrapAround;
+
+/// Respawns copies of the cells near the seams whenever the `GridMap` or the
+/// cell meshes change, so a wrapping grid looks continuous when looked at
+/// across its edges
+#[cfg(feature = "render")]
+pub fn mirror_seams<K, T>(
+    mut commands: Commands,
+    grid: Res<GridConfig<T>>,
+    map: Res<GridMap<K>>,
+    cells: Query<(&Handle<Mesh>, &Handle<StandardMaterial>, &Transform)>,
+    meshed: Query<(), (Changed<Handle<Mesh>>, Without<SeamCopy>)>,
+    copies: Query<Entity, With<SeamCopy>>,
+) where
+    K: Coords + Copy + Eq + Hash + Send + Sync + 'static,
+    T: GridPrimitive + Topology<K> + Send + Sync + 'static,
+{
+    if !map.is_changed() && meshed.is_empty() {
+        return;
+    }
+    for copy in copies.iter() {
+        commands.entity(copy).despawn();
+    }
+    let grid = &grid.0;
+    let wrap = grid.wrapping();
+    let (period_x, period_y) = grid.world_period();
+    for (coord, entity) in map.iter() {
+        let Ok((mesh, material, transform)) = cells.get(*entity) else {
+            continue;
+        };
+        let (col, row) = grid.offset(&grid.normalize(coord));
+        let mut shifts = Vec::new();
+        if let (Some(w), Some(p)) = (wrap.width, period_x) {
+            if col < SEAM_MARGIN {
+                shifts.push(p);
+            }
+            if col >= w as i32 - SEAM_MARGIN {
+                shifts.push(-p);
+            }
+        }
+        if let (Some(h), Some(p)) = (wrap.height, period_y) {
+            let horizontal = shifts.clone();
+            if row < SEAM_MARGIN {
+                shifts.push(p);
+                shifts.extend(horizontal.iter().map(|s| *s + p));
+            }
+            if row >= h as i32 - SEAM_MARGIN {
+                shifts.push(-p);
+                shifts.extend(horizontal.iter().map(|s| *s - p));
+            }
+        }
+        for shift in shifts {
+            let mut transform = *transform;
+            transform.translation += shift;
+            commands.spawn((
+                PbrBundle {
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    transform,
+                    ..default()
+                },
+                SeamCopy(*entity),
+            ));
+        }
+    }
+}
+
+/// Keeps seam copies showing the same material and visibility as their cell
+#[cfg(feature = "render")]
+pub fn sync_seam_copies(
+    mut copies: Query<(&SeamCopy, &mut Handle<StandardMaterial>, &mut Visibility)>,
+    cells: Query<(&Handle<StandardMaterial>, &Visibility), Without<SeamCopy>>,
+) {
+    for (copy, mut material, mut visibility) in copies.iter_mut() {
+        if let Ok((cell_material, cell_visibility)) = cells.get(copy.0) {
+            if *material != *cell_material {
+                *material = cell_material.clone();
+            }
+            if *visibility != *cell_visibility {
+                *visibility = *cell_visibility;
+            }
+        }
+    }
+}
+
+/// Shifts `WrapAround` entities by a map width or height when they leave the map
+pub fn wrap_around<K, T>(
+    grid: Res<GridConfig<T>>,
+    mut query: Query<&mut Transform, With<WrapAround>>,
+) where
+    K: Coords + Copy,
+    T: GridPrimitive + Topology<K> + Send + Sync + 'static,
+{
+    let grid = &grid.0;
+    let (period_x, period_y) = grid.world_period();
+    let origin = grid.cell_pos(&grid.normalize(&K::ZERO));
+    for mut transform in query.iter_mut() {
+        for period in [period_x, period_y].into_iter().flatten() {
+            let along = (transform.translation - origin).dot(period) / period.length_squared();
+            if !(0.0..1.0).contains(&along) {
+                transform.translation -= period * along.floor();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::primitives::GridAlign;
+
+    fn hexes(orientation: HexOrientation) -> Hexes {
+        Hexes {
+            size: 1.0,
+            alignment: GridAlign::XY,
+            orientation,
+            layer: 0.0,
+            wrap: GridWrap {
+                width: Some(10),
+                height: Some(6),
+            },
+        }
+    }
+
+    #[test]
+    fn hexes_wrap_across_seams() {
+        for orientation in [HexOrientation::PointyUp, HexOrientation::FlatUp] {
+            let grid = hexes(orientation);
+            let west = grid.translate(&HexCoord::ZERO, 0, 0);
+            let east = grid.normalize(&HexCoord { q: -1, r: 0 });
+            assert_eq!(grid.offset(&east).0, 9);
+            assert_eq!(grid.distance(&west, &east), 1);
+            assert!(grid.neighbours(&west).contains(&east));
+            assert_eq!(grid.line(&west, &east), vec![west, east]);
+
+            let (period_x, period_y) = grid.world_period();
+            let far = grid.translate(&HexCoord { q: 2, r: 3 }, 1, 1);
+            let shift = grid.cell_pos(&far) - grid.cell_pos(&HexCoord { q: 2, r: 3 });
+            assert!(shift.distance(period_x.unwrap() + period_y.unwrap()) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn triangles_keep_their_orientation() {
+        let grid = Triangles {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            neighbors: Default::default(),
+            layer: 0.0,
+            wrap: GridWrap {
+                width: Some(5),
+                height: Some(4),
+            },
+        };
+        let t = TriangleCoord::new(6, -1, true);
+        let wrapped = grid.normalize(&t);
+        assert_eq!(wrapped, TriangleCoord::new(1, 3, true));
+        assert_eq!(wrapped.points_up(), t.points_up());
+        assert_eq!(
+            grid.distance(
+                &TriangleCoord::new(0, 0, false),
+                &TriangleCoord::new(4, 0, true)
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn wrap_sizes_are_checked() {
+        let mut pointy = hexes(HexOrientation::PointyUp);
+        let mut flat = hexes(HexOrientation::FlatUp);
+        assert_eq!(pointy.check_wrap(), Ok(()));
+        assert_eq!(flat.check_wrap(), Ok(()));
+        pointy.wrap.height = Some(5);
+        flat.wrap.height = Some(5);
+        assert_eq!(pointy.check_wrap(), Err(WrapError::Odd("height", 5)));
+        assert_eq!(flat.check_wrap(), Ok(()));
+        flat.wrap.width = Some(0);
+        assert_eq!(flat.check_wrap(), Err(WrapError::Empty("width")));
+    }
+}
@@ -0,0 +1,440 @@
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use std::{hash::Hash, marker::PhantomData};
+
+use super::{
+    coordinates::Coords,
+    fog::Faction,
+    pathfinding::{find_path, reachable},
+    selection::Selection,
+    terrain::Terrains,
+    units::{MoveAlong, MoveSpeed, Occupancy, DEFAULT_SPEED},
+    GridConfig, GridLayout, GridMap, GridPosition,
+};
+
+/// Where the player whose turn it is stands
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TurnPhase {
+    /// Action points are about to be restored
+    Start,
+    /// Units take orders
+    Orders,
+    /// The turn was ended and passes on once every unit has stopped moving
+    Ending,
+}
+
+/// Whose turn it is, going round the players in order
+#[derive(Resource, Clone, Debug)]
+pub struct Turn {
+    /// Round of turns, from 1
+    pub number: u32,
+    pub phase: TurnPhase,
+    players: Vec<Faction>,
+    current: usize,
+}
+
+impl Turn {
+    /// Turns of the `first` player, then of the `rest` in order
+    pub fn new(first: Faction, rest: impl IntoIterator<Item = Faction>) -> Self {
+        Turn {
+            number: 1,
+            phase: TurnPhase::Start,
+            players: std::iter::once(first).chain(rest).collect(),
+            current: 0,
+        }
+    }
+
+    pub fn player(&self) -> Faction {
+        self.players[self.current]
+    }
+
+    pub fn players(&self) -> &[Faction] {
+        &self.players
+    }
+}
+
+/// Cells a unit may still walk this turn, restored at the start of the turns
+/// of its `Faction`
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ActionPoints {
+    pub left: u32,
+    pub max: u32,
+}
+
+impl ActionPoints {
+    pub fn new(max: u32) -> Self {
+        ActionPoints { left: max, max }
+    }
+}
+
+/// Asks for the turn of the current player to end
+#[derive(Clone, Copy, Debug)]
+pub struct EndTurn;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TurnStarted {
+    pub number: u32,
+    pub player: Faction,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TurnEnded {
+    pub number: u32,
+    pub player: Faction,
+}
+
+/// Asks for a unit of the current player to walk to a cell, if its action
+/// points cover the way there
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MoveOrder<K> {
+    pub unit: Entity,
+    pub to: K,
+}
+
+/// Unit whose moves are shown, and taken by selecting a cell it can reach
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ActiveUnit(pub Option<Entity>);
+
+/// Cells the `ActiveUnit` can reach this turn, with what it costs to get there
+#[derive(Resource, Clone, Debug)]
+pub struct Reachable<K: Coords>(HashMap<K, u32>);
+
+impl<K: Coords + Eq + Hash> Reachable<K> {
+    pub fn cost(&self, coord: &K) -> Option<u32> {
+        self.0.get(coord).copied()
+    }
+
+    pub fn contains(&self, coord: &K) -> bool {
+        self.0.contains_key(coord)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &u32)> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<K: Coords> Default for Reachable<K> {
+    fn default() -> Self {
+        Reachable(HashMap::new())
+    }
+}
+
+/// Turns of the players on the grid of `T`, in the order of their `Turn`.
+/// Units with a `Faction` and `ActionPoints` take `MoveOrder`s on their
+/// turns, or clicks along with a `SelectionPlugin`: selecting a unit makes it
+/// the `ActiveUnit`, then selecting one of the `Reachable` cells sends it
+/// there. Needs the `UnitPlugin` of the same grid.
+pub struct TurnPlugin<T> {
+    turn: Turn,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> TurnPlugin<T> {
+    /// Turns of the `first` player, then of the `rest` in order
+    pub fn new(first: Faction, rest: impl IntoIterator<Item = Faction>) -> Self {
+        TurnPlugin {
+            turn: Turn::new(first, rest),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: GridLayout> Plugin for TurnPlugin<T> {
+    fn build(&self, app: &mut App) {
+        let configured = resource_exists::<GridConfig<T>>;
+        app.insert_resource(self.turn.clone())
+            .init_resource::<ActiveUnit>()
+            .insert_resource(Reachable::<T::Coord>::default())
+            .add_event::<EndTurn>()
+            .add_event::<TurnStarted>()
+            .add_event::<TurnEnded>()
+            .add_event::<MoveOrder<T::Coord>>()
+            .add_system(order_moves::<T>.run_if(configured()))
+            .add_system(
+                advance_turns::<T::Coord>
+                    .after(order_moves::<T>)
+                    .before(update_reachable::<T>),
+            )
+            .add_system(update_reachable::<T>.run_if(configured()))
+            .add_system(
+                select_units::<T::Coord>
+                    .before(order_moves::<T>)
+                    .run_if(resource_exists::<Selection<T::Coord>>()),
+            );
+    }
+}
+
+/// Prices of entering cells when ordered to move: the costs of their terrain,
+/// on cells of the map with room for one more unit
+#[derive(SystemParam)]
+pub struct MoveCosts<'w, K: Coords + Copy + Eq + Hash + Send + Sync + 'static> {
+    map: Res<'w, GridMap<K>>,
+    occupancy: Res<'w, Occupancy<K>>,
+    terrains: Terrains<'w, K>,
+}
+
+impl<'w, K: Coords + Copy + Eq + Hash + Send + Sync + 'static> MoveCosts<'w, K> {
+    pub fn cost(&self, coord: &K) -> Option<u32> {
+        if self.map.get(coord).is_none() || !self.occupancy.has_room(coord) {
+            return None;
+        }
+        self.terrains.cost(coord)
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.map.is_changed() || self.occupancy.is_changed() || self.terrains.is_changed()
+    }
+}
+
+/// Makes a unit of the current player the `ActiveUnit` when its cell is
+/// selected, or sends the `ActiveUnit` to the cell selected otherwise
+pub fn select_units<K: Coords + Copy + Eq + Hash + Send + Sync + 'static>(
+    selection: Res<Selection<K>>,
+    turn: Res<Turn>,
+    occupancy: Res<Occupancy<K>>,
+    reachable: Res<Reachable<K>>,
+    units: Query<&Faction, With<ActionPoints>>,
+    mut active: ResMut<ActiveUnit>,
+    mut orders: EventWriter<MoveOrder<K>>,
+) {
+    if !selection.is_changed() || selection.len() != 1 {
+        return;
+    }
+    let cell = *selection.iter().next().unwrap();
+    let own = occupancy
+        .units(&cell)
+        .iter()
+        .find(|unit| units.get(**unit).is_ok_and(|f| *f == turn.player()));
+    if let Some(unit) = own {
+        active.0 = Some(*unit);
+    } else if let Some(unit) = active.0.filter(|_| reachable.contains(&cell)) {
+        orders.send(MoveOrder { unit, to: cell });
+    }
+}
+
+/// Units able to take orders, with how fast they walk
+type Idle<'a, K> = (
+    &'a GridPosition<K>,
+    &'a Faction,
+    &'a mut ActionPoints,
+    Option<&'a MoveSpeed>,
+);
+
+/// Units whose reach is shown
+type Standing<'a, K> = (&'a GridPosition<K>, &'a Faction, &'a ActionPoints);
+
+/// Units that may have a new reach
+type Walked<K> = Or<(
+    Changed<ActionPoints>,
+    Changed<GridPosition<K>>,
+    Added<MoveAlong<K>>,
+)>;
+
+pub fn order_moves<T: GridLayout>(
+    mut commands: Commands,
+    mut orders: EventReader<MoveOrder<T::Coord>>,
+    turn: Res<Turn>,
+    grid: Res<GridConfig<T>>,
+    costs: MoveCosts<T::Coord>,
+    mut units: Query<Idle<T::Coord>, Without<MoveAlong<T::Coord>>>,
+) {
+    let mut ordered = HashSet::new();
+    for order in orders.iter() {
+        if turn.phase != TurnPhase::Orders || !ordered.insert(order.unit) {
+            continue;
+        }
+        let Ok((position, faction, mut points, speed)) = units.get_mut(order.unit) else {
+            continue;
+        };
+        if *faction != turn.player() {
+            continue;
+        }
+        let Some(path) = find_path(&grid.0, position.0, order.to, |c| costs.cost(c)) else {
+            continue;
+        };
+        if path.cost > points.left {
+            continue;
+        }
+        points.left -= path.cost;
+        let speed = speed.map_or(DEFAULT_SPEED, |s| s.0);
+        commands
+            .entity(order.unit)
+            .insert(MoveAlong::new(path, speed));
+    }
+}
+
+/// Ends turns when asked to, once no unit is moving any more, and starts the
+/// turn of the next player
+pub fn advance_turns<K: Coords + Send + Sync + 'static>(
+    mut turn: ResMut<Turn>,
+    mut ends: EventReader<EndTurn>,
+    moving: Query<(), With<MoveAlong<K>>>,
+    mut units: Query<(&Faction, &mut ActionPoints)>,
+    mut active: ResMut<ActiveUnit>,
+    mut started: EventWriter<TurnStarted>,
+    mut ended: EventWriter<TurnEnded>,
+) {
+    // units ordered to move on the frame the turn ends only show up on the next
+    if turn.phase == TurnPhase::Ending && moving.is_empty() {
+        ended.send(TurnEnded {
+            number: turn.number,
+            player: turn.player(),
+        });
+        turn.current = (turn.current + 1) % turn.players.len();
+        if turn.current == 0 {
+            turn.number += 1;
+        }
+        turn.phase = TurnPhase::Start;
+        active.0 = None;
+    }
+    if ends.iter().count() > 0 && turn.phase == TurnPhase::Orders {
+        turn.phase = TurnPhase::Ending;
+    }
+    if turn.phase == TurnPhase::Start {
+        let player = turn.player();
+        for (_, mut points) in units.iter_mut().filter(|(f, _)| **f == player) {
+            points.left = points.max;
+        }
+        started.send(TurnStarted {
+            number: turn.number,
+            player,
+        });
+        turn.phase = TurnPhase::Orders;
+    }
+}
+
+/// Cells the `ActiveUnit` can walk to, while it belongs to the current
+/// player and stands still
+#[allow(clippy::too_many_arguments)]
+pub fn update_reachable<T: GridLayout>(
+    grid: Res<GridConfig<T>>,
+    costs: MoveCosts<T::Coord>,
+    turn: Res<Turn>,
+    active: Res<ActiveUnit>,
+    units: Query<Standing<T::Coord>, Without<MoveAlong<T::Coord>>>,
+    changed: Query<(), Walked<T::Coord>>,
+    mut stopped: RemovedComponents<MoveAlong<T::Coord>>,
+    mut shown: ResMut<Reachable<T::Coord>>,
+) {
+    let stopped = stopped.iter().count() > 0;
+    if !stopped
+        && changed.is_empty()
+        && !(active.is_changed() || turn.is_changed() || costs.is_changed())
+    {
+        return;
+    }
+    let cells = match active.0.and_then(|unit| units.get(unit).ok()) {
+        Some((position, faction, points)) if *faction == turn.player() => {
+            let mut cells = reachable(&grid.0, position.0, points.left, |c| costs.cost(c));
+            cells.remove(&position.0);
+            cells
+        }
+        _ => HashMap::new(),
+    };
+    if shown.0 != cells {
+        shown.0 = cells;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::SquareCoord,
+        primitives::{GridAlign, GridWrap, Squares},
+        testing::{spawn_map, square, tick},
+        units::UnitPlugin,
+        GridPlugin,
+    };
+    use bevy::utils::Instant;
+
+    #[test]
+    fn players_take_turns() {
+        let squares = Squares {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let (red, blue) = (Faction(0), Faction(1));
+        let mut app = App::new();
+        app.insert_resource(Time::new(Instant::now()))
+            .add_plugin(GridPlugin::new(squares))
+            .add_plugin(UnitPlugin::<Squares>::default())
+            .add_plugin(TurnPlugin::<Squares>::new(red, [blue]))
+            .add_startup_system(spawn_map::<Squares>(5, 5));
+        let knight = app
+            .world
+            .spawn((
+                GridPosition(square(0, 0)),
+                TransformBundle::default(),
+                red,
+                ActionPoints::new(3),
+            ))
+            .id();
+        let archer = app
+            .world
+            .spawn((
+                GridPosition(square(4, 4)),
+                TransformBundle::default(),
+                blue,
+                ActionPoints { left: 0, max: 2 },
+            ))
+            .id();
+        tick(&mut app, 0.0);
+        let turn = app.world.resource::<Turn>();
+        assert_eq!((turn.number, turn.player()), (1, red));
+        assert_eq!(turn.phase, TurnPhase::Orders);
+
+        app.world.resource_mut::<ActiveUnit>().0 = Some(knight);
+        tick(&mut app, 0.0);
+        // corner of a diamond three cells wide
+        let reach = app.world.resource::<Reachable<SquareCoord>>();
+        assert_eq!(reach.iter().count(), 9);
+        assert_eq!(reach.cost(&square(1, 2)), Some(3));
+
+        app.world.send_event(MoveOrder {
+            unit: archer,
+            to: square(4, 3),
+        });
+        app.world.send_event(MoveOrder {
+            unit: knight,
+            to: square(2, 0),
+        });
+        app.world.send_event(EndTurn);
+        tick(&mut app, 0.0);
+        assert_eq!(app.world.get::<ActionPoints>(knight).unwrap().left, 1);
+        assert!(app.world.get::<MoveAlong<SquareCoord>>(archer).is_none());
+        // the turn waits for the knight to get there
+        tick(&mut app, 0.0);
+        assert_eq!(app.world.resource::<Turn>().phase, TurnPhase::Ending);
+        assert!(app.world.resource::<Reachable<SquareCoord>>().is_empty());
+
+        tick(&mut app, 1.0);
+        tick(&mut app, 0.0);
+        assert_eq!(
+            app.world
+                .get::<GridPosition<SquareCoord>>(knight)
+                .unwrap()
+                .0,
+            square(2, 0)
+        );
+        let turn = app.world.resource::<Turn>();
+        assert_eq!((turn.number, turn.player()), (1, blue));
+        assert_eq!(app.world.get::<ActionPoints>(archer).unwrap().left, 2);
+
+        app.world.send_event(EndTurn);
+        tick(&mut app, 0.0);
+        tick(&mut app, 0.0);
+        let turn = app.world.resource::<Turn>();
+        assert_eq!((turn.number, turn.player()), (2, red));
+        assert_eq!(app.world.get::<ActionPoints>(knight).unwrap().left, 3);
+    }
+}
@@ -0,0 +1,432 @@
+use bevy::{prelude::*, utils::HashMap};
+use std::{hash::Hash, marker::PhantomData};
+
+use super::{coordinates::Coords, pathfinding::Path, GridConfig, GridLayout, GridPosition};
+
+/// Units standing on every cell, at most `limit` to a cell unless the cell
+/// has a limit of its own. Units moved by `MoveAlong` wait for room; placing
+/// one by its `GridPosition` always succeeds, over the limit if need be.
+#[derive(Resource)]
+pub struct Occupancy<K: Coords> {
+    pub limit: usize,
+    limits: HashMap<K, usize>,
+    cells: HashMap<K, Vec<Entity>>,
+    units: HashMap<Entity, K>,
+}
+
+impl<K: Coords + Copy + Eq + Hash> Occupancy<K> {
+    pub fn new(limit: usize) -> Self {
+        Occupancy {
+            limit,
+            limits: HashMap::new(),
+            cells: HashMap::new(),
+            units: HashMap::new(),
+        }
+    }
+
+    /// Units on the cell, in the order they entered it
+    pub fn units(&self, coord: &K) -> &[Entity] {
+        self.cells.get(coord).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn cell_of(&self, unit: Entity) -> Option<K> {
+        self.units.get(&unit).copied()
+    }
+
+    pub fn limit_of(&self, coord: &K) -> usize {
+        self.limits.get(coord).copied().unwrap_or(self.limit)
+    }
+
+    /// Overrides the limit of one cell, e.g. 0 for cells no unit may enter
+    pub fn set_limit(&mut self, coord: K, limit: usize) {
+        self.limits.insert(coord, limit);
+    }
+
+    pub fn has_room(&self, coord: &K) -> bool {
+        self.units(coord).len() < self.limit_of(coord)
+    }
+
+    /// Puts `unit` on `coord`, returning the cell it stood on before
+    pub fn place(&mut self, unit: Entity, coord: K) -> Option<K> {
+        let from = self.remove(unit);
+        self.cells.entry(coord).or_default().push(unit);
+        self.units.insert(unit, coord);
+        from
+    }
+
+    /// Takes `unit` off the board, returning the cell it stood on
+    pub fn remove(&mut self, unit: Entity) -> Option<K> {
+        let from = self.units.remove(&unit)?;
+        if let Some(units) = self.cells.get_mut(&from) {
+            units.retain(|u| *u != unit);
+            if units.is_empty() {
+                self.cells.remove(&from);
+            }
+        }
+        Some(from)
+    }
+}
+
+impl<K: Coords + Copy + Eq + Hash> Default for Occupancy<K> {
+    fn default() -> Self {
+        Occupancy::new(1)
+    }
+}
+
+/// A unit came onto a cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellEntered<K> {
+    pub unit: Entity,
+    pub cell: K,
+}
+
+/// A unit went off a cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellLeft<K> {
+    pub unit: Entity,
+    pub cell: K,
+}
+
+/// Cells a second a unit walks when ordered to move, `DEFAULT_SPEED` without it
+#[derive(Component, Clone, Copy, Debug)]
+pub struct MoveSpeed(pub f32);
+
+pub const DEFAULT_SPEED: f32 = 4.0;
+
+/// Walks a unit along the cells of a path, `speed` cells a second, updating
+/// its `GridPosition` as it enters each of them. Removed once the unit arrives.
+#[derive(Component, Clone, Debug)]
+pub struct MoveAlong<K> {
+    cells: Vec<K>,
+    /// Cell being left
+    step: usize,
+    /// Part of the way to the next cell already walked
+    progress: f32,
+    pub speed: f32,
+}
+
+impl<K: Coords + Copy> MoveAlong<K> {
+    /// Movement along a path found from the cell the unit stands on. An
+    /// empty path goes nowhere: the unit stays put and the `MoveAlong` is
+    /// removed.
+    pub fn new(path: Path<K>, speed: f32) -> Self {
+        MoveAlong {
+            cells: path.cells,
+            step: 0,
+            progress: 0.0,
+            speed,
+        }
+    }
+
+    /// Cells still to enter
+    pub fn remaining(&self) -> &[K] {
+        &self.cells[(self.step + 1).min(self.cells.len())..]
+    }
+
+    /// Walks on to one more cell, bordering the last one, if still on the way
+    pub fn push(&mut self, cell: K) {
+        self.cells.push(cell);
+    }
+}
+
+/// Units on the grid of `T`: an `Occupancy` index of their `GridPosition`s
+/// and their walks along paths
+pub struct UnitPlugin<T> {
+    limit: usize,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> UnitPlugin<T> {
+    /// Units stacking up to `limit` to a cell
+    pub fn new(limit: usize) -> Self {
+        UnitPlugin {
+            limit,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for UnitPlugin<T> {
+    fn default() -> Self {
+        UnitPlugin::new(1)
+    }
+}
+
+impl<T: GridLayout> Plugin for UnitPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Occupancy::<T::Coord>::new(self.limit))
+            .add_event::<CellEntered<T::Coord>>()
+            .add_event::<CellLeft<T::Coord>>()
+            .add_system(index_units::<T::Coord>)
+            .add_system(
+                move_units::<T>
+                    .after(index_units::<T::Coord>)
+                    .run_if(resource_exists::<GridConfig<T>>()),
+            );
+    }
+}
+
+/// Keeps the `Occupancy` in step with units placed, moved or taken off by
+/// hand
+pub fn index_units<K: Coords + Copy + Eq + Hash + Send + Sync + 'static>(
+    mut occupancy: ResMut<Occupancy<K>>,
+    placed: Query<(Entity, &GridPosition<K>), Changed<GridPosition<K>>>,
+    mut removed: RemovedComponents<GridPosition<K>>,
+    mut entered: EventWriter<CellEntered<K>>,
+    mut left: EventWriter<CellLeft<K>>,
+) {
+    for unit in removed.iter() {
+        if let Some(cell) = occupancy.remove(unit) {
+            left.send(CellLeft { unit, cell });
+        }
+    }
+    for (unit, position) in placed.iter() {
+        let from = occupancy.cell_of(unit);
+        if from == Some(position.0) {
+            continue;
+        }
+        occupancy.place(unit, position.0);
+        if let Some(cell) = from {
+            left.send(CellLeft { unit, cell });
+        }
+        entered.send(CellEntered {
+            unit,
+            cell: position.0,
+        });
+    }
+}
+
+/// Units on their way, with where they stand
+type Walkers<'a, K> = (
+    Entity,
+    &'a mut MoveAlong<K>,
+    &'a mut GridPosition<K>,
+    &'a mut Transform,
+);
+
+pub fn move_units<T: GridLayout>(
+    mut commands: Commands,
+    time: Res<Time>,
+    grid: Res<GridConfig<T>>,
+    mut occupancy: ResMut<Occupancy<T::Coord>>,
+    mut units: Query<Walkers<T::Coord>>,
+    mut entered: EventWriter<CellEntered<T::Coord>>,
+    mut left: EventWriter<CellLeft<T::Coord>>,
+) {
+    let at = |cell: &T::Coord| grid.0.cell_transform(cell).translation;
+    for (unit, mut walk, mut position, mut transform) in units.iter_mut() {
+        walk.progress += walk.speed * time.delta_seconds();
+        while walk.progress >= 1.0 && walk.step + 1 < walk.cells.len() {
+            let next = walk.cells[walk.step + 1];
+            if !occupancy.has_room(&next) {
+                // wait in the cell for room
+                walk.progress = 0.0;
+                break;
+            }
+            walk.progress -= 1.0;
+            walk.step += 1;
+            if let Some(cell) = occupancy.place(unit, next) {
+                left.send(CellLeft { unit, cell });
+            }
+            entered.send(CellEntered { unit, cell: next });
+            position.0 = next;
+        }
+        let Some(here) = walk.cells.get(walk.step).map(|c| grid.0.normalize(c)) else {
+            commands.entity(unit).remove::<MoveAlong<T::Coord>>();
+            continue;
+        };
+        let from = at(&here);
+        match walk.cells.get(walk.step + 1) {
+            Some(next) => {
+                // towards the copy of the next cell across any seam
+                let next = grid.0.nearest_image(&here, next);
+                transform.translation = from.lerp(at(&next), walk.progress);
+            }
+            None => {
+                transform.translation = from;
+                commands.entity(unit).remove::<MoveAlong<T::Coord>>();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::SquareCoord,
+        pathfinding::find_path,
+        primitives::{GridAlign, GridWrap, Squares},
+        testing::{square, tick},
+        topology::Topology,
+        GridPlugin,
+    };
+    use bevy::utils::Instant;
+
+    /// Cells entered and left, in order
+    #[derive(Resource, Default)]
+    struct Log(Vec<(SquareCoord, bool)>);
+
+    fn log(
+        mut log: ResMut<Log>,
+        mut entered: EventReader<CellEntered<SquareCoord>>,
+        mut left: EventReader<CellLeft<SquareCoord>>,
+    ) {
+        log.0.extend(left.iter().map(|e| (e.cell, false)));
+        log.0.extend(entered.iter().map(|e| (e.cell, true)));
+    }
+
+    /// App on a clock that only moves when told to
+    fn app() -> (App, Squares) {
+        let squares = Squares {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let mut app = App::new();
+        app.insert_resource(Time::new(Instant::now()))
+            .add_plugin(GridPlugin::new(squares))
+            .add_plugin(UnitPlugin::<Squares>::default())
+            .init_resource::<Log>()
+            .add_system(log.after(move_units::<Squares>));
+        (app, squares)
+    }
+
+    fn walk(app: &mut App, squares: &Squares, from: SquareCoord, to: SquareCoord) -> Entity {
+        let path = find_path(squares, from, to, |_| Some(1)).unwrap();
+        app.world
+            .spawn((
+                GridPosition(from),
+                TransformBundle::from_transform(squares.cell_transform(&from)),
+                MoveAlong::new(path, 2.0),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn units_walk_paths() {
+        let (mut app, squares) = app();
+        let unit = walk(&mut app, &squares, square(0, 0), square(2, 0));
+        tick(&mut app, 0.0);
+        assert_eq!(
+            app.world
+                .resource::<Occupancy<SquareCoord>>()
+                .units(&square(0, 0)),
+            [unit]
+        );
+
+        // half a cell in a quarter of a second
+        tick(&mut app, 0.25);
+        let halfway = squares
+            .cell_pos(&square(0, 0))
+            .lerp(squares.cell_pos(&square(1, 0)), 0.5);
+        assert!(app
+            .world
+            .get::<Transform>(unit)
+            .unwrap()
+            .translation
+            .abs_diff_eq(halfway, 1e-4));
+        assert_eq!(
+            app.world.get::<GridPosition<SquareCoord>>(unit).unwrap().0,
+            square(0, 0)
+        );
+
+        tick(&mut app, 0.5);
+        assert_eq!(
+            app.world.get::<GridPosition<SquareCoord>>(unit).unwrap().0,
+            square(1, 0)
+        );
+        tick(&mut app, 1.0);
+        assert!(app.world.get::<MoveAlong<SquareCoord>>(unit).is_none());
+        let end = app.world.get::<Transform>(unit).unwrap().translation;
+        assert_eq!(end, squares.cell_pos(&square(2, 0)));
+
+        let occupancy = app.world.resource::<Occupancy<SquareCoord>>();
+        assert_eq!(occupancy.units(&square(2, 0)), [unit]);
+        assert!(occupancy.units(&square(0, 0)).is_empty());
+        let log = &app.world.resource::<Log>().0;
+        let (a, b, c) = (square(0, 0), square(1, 0), square(2, 0));
+        assert_eq!(
+            log,
+            &[(a, true), (a, false), (b, true), (b, false), (c, true)]
+        );
+    }
+
+    #[test]
+    fn units_walk_across_seams() {
+        let (mut app, _) = app();
+        let squares = Squares {
+            wrap: GridWrap {
+                width: Some(8),
+                height: None,
+            },
+            ..app.world.resource::<GridConfig<Squares>>().0
+        };
+        app.insert_resource(GridConfig(squares));
+        let unit = walk(&mut app, &squares, square(7, 0), square(0, 0));
+        assert_eq!(
+            app.world
+                .get::<MoveAlong<SquareCoord>>(unit)
+                .unwrap()
+                .remaining(),
+            [square(0, 0)]
+        );
+        tick(&mut app, 0.0);
+        tick(&mut app, 0.25);
+        // halfway to the copy of the first column past the last one
+        let halfway = squares
+            .cell_pos(&square(7, 0))
+            .lerp(squares.cell_pos(&square(8, 0)), 0.5);
+        let pos = app.world.get::<Transform>(unit).unwrap().translation;
+        assert!(pos.abs_diff_eq(halfway, 1e-4), "{pos}");
+
+        let idle = app
+            .world
+            .spawn((
+                GridPosition(square(3, 0)),
+                TransformBundle::default(),
+                MoveAlong::<SquareCoord>::new(
+                    Path {
+                        cells: Vec::new(),
+                        cost: 0,
+                    },
+                    2.0,
+                ),
+            ))
+            .id();
+        tick(&mut app, 0.25);
+        assert!(app.world.get::<MoveAlong<SquareCoord>>(idle).is_none());
+        assert_eq!(
+            app.world.get::<GridPosition<SquareCoord>>(idle).unwrap().0,
+            square(3, 0)
+        );
+    }
+
+    #[test]
+    fn units_wait_for_room() {
+        let (mut app, squares) = app();
+        let guard = app.world.spawn(GridPosition(square(1, 0))).id();
+        let unit = walk(&mut app, &squares, square(0, 0), square(2, 0));
+        tick(&mut app, 0.0);
+        tick(&mut app, 2.0);
+        assert_eq!(
+            app.world.get::<GridPosition<SquareCoord>>(unit).unwrap().0,
+            square(0, 0)
+        );
+        assert_eq!(
+            app.world
+                .get::<MoveAlong<SquareCoord>>(unit)
+                .unwrap()
+                .remaining(),
+            [square(1, 0), square(2, 0)]
+        );
+
+        app.world.despawn(guard);
+        tick(&mut app, 1.0);
+        assert_eq!(
+            app.world.get::<GridPosition<SquareCoord>>(unit).unwrap().0,
+            square(2, 0)
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use bevy::utils::{HashMap, HashSet};
+use std::{collections::VecDeque, hash::Hash};
+
+use super::coordinates::{Coords, HexCoord, SquareCoord, TriangleCoord};
+
+/// Value of the `GridLayer` marking the cells nothing can be seen through
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Opaque;
+
+/// Field of view and line of sight over cells marked opaque by a predicate.
+/// Opaque cells block the view past them but are visible themselves. A cell
+/// is in the field of view exactly when there is a line of sight to it.
+pub trait FieldOfView: Coords + Copy + Eq + Hash {
+    fn has_line_of_sight<F: Fn(&Self) -> bool>(&self, other: &Self, opaque: F) -> bool {
+        let line = self.line_to(other);
+        let between = line.len().saturating_sub(2);
+        !line.iter().skip(1).take(between).any(opaque)
+    }
+
+    /// Cells within `radius` of &self that can be seen from it
+    fn field_of_view<F: Fn(&Self) -> bool>(&self, radius: u32, opaque: F) -> HashSet<Self> {
+        within(*self, radius)
+            .into_iter()
+            .filter(|c| self.has_line_of_sight(c, &opaque))
+            .collect()
+    }
+}
+
+impl FieldOfView for HexCoord {}
+
+impl FieldOfView for TriangleCoord {}
+
+impl FieldOfView for SquareCoord {
+    /// Whether shadowcasting reaches `other`, rather than `line_to`, which
+    /// lets the view through gaps the field of view counts as shadow. Light
+    /// is only cast between the slopes bracketing `other`, in the octants
+    /// holding it, so a check scans about as many cells as `other` is away.
+    fn has_line_of_sight<F: Fn(&Self) -> bool>(&self, other: &Self, opaque: F) -> bool {
+        if other == self {
+            return true;
+        }
+        let (q, r) = (other.q - self.q, other.r - self.r);
+        let mut seen = false;
+        for [xx, xy, yx, yy] in OCTANTS {
+            // the transforms are their own transposed inverses
+            let (dx, dy) = (q * xx + r * yx, q * xy + r * yy);
+            if dy >= 0 || dx < dy || dx > 0 {
+                continue;
+            }
+            let octant = Octant {
+                origin: *self,
+                radius: self.distance(other) as i32,
+                depth: -dy,
+                transform: (xx, xy, yx, yy),
+            };
+            let (left, right) = slopes(dx, dy);
+            octant.cast_light(1, left.min(1.0), right.max(0.0), &opaque, &mut |cell| {
+                seen |= cell == *other
+            });
+            if seen {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Recursive shadowcasting, one octant at a time
+    fn field_of_view<F: Fn(&Self) -> bool>(&self, radius: u32, opaque: F) -> HashSet<Self> {
+        let mut visible = HashSet::new();
+        visible.insert(*self);
+        for [xx, xy, yx, yy] in OCTANTS {
+            let octant = Octant {
+                origin: *self,
+                radius: radius as i32,
+                depth: radius as i32,
+                transform: (xx, xy, yx, yy),
+            };
+            octant.cast_light(1, 1.0, 0.0, &opaque, &mut |cell| {
+                visible.insert(cell);
+            });
+        }
+        visible
+    }
+}
+
+/// Every cell at most `radius` steps away from `origin`
+fn within<K: Coords + Copy + Eq + Hash>(origin: K, radius: u32) -> Vec<K> {
+    let mut seen = HashMap::new();
+    seen.insert(origin, 0);
+    let mut queue = VecDeque::from([origin]);
+    while let Some(cell) = queue.pop_front() {
+        let steps = seen[&cell];
+        if steps == radius {
+            continue;
+        }
+        for n in cell.neighbours() {
+            if !seen.contains_key(&n) {
+                seen.insert(n, steps + 1);
+                queue.push_back(n);
+            }
+        }
+    }
+    seen.into_keys().collect()
+}
+
+const OCTANTS: [[i32; 4]; 8] = [
+    [1, 0, 0, 1],
+    [0, 1, 1, 0],
+    [0, -1, 1, 0],
+    [-1, 0, 0, 1],
+    [-1, 0, 0, -1],
+    [0, -1, -1, 0],
+    [0, 1, -1, 0],
+    [1, 0, 0, -1],
+];
+
+struct Octant {
+    origin: SquareCoord,
+    radius: i32,
+    /// Last row scanned
+    depth: i32,
+    transform: (i32, i32, i32, i32),
+}
+
+/// Slopes of the left and right edges of the cell `dx` across in row `-dy`
+/// of an octant
+fn slopes(dx: i32, dy: i32) -> (f32, f32) {
+    let (dx, dy) = (dx as f32, dy as f32);
+    ((dx - 0.5) / (dy + 0.5), (dx + 0.5) / (dy - 0.5))
+}
+
+impl Octant {
+    fn cell(&self, dx: i32, dy: i32) -> SquareCoord {
+        let (xx, xy, yx, yy) = self.transform;
+        SquareCoord {
+            q: self.origin.q + dx * xx + dy * xy,
+            r: self.origin.r + dx * yx + dy * yy,
+        }
+    }
+
+    /// Scans rows `row..=depth` between the `start` and `end` slopes, handing
+    /// the cells within `radius` it lights to `see` and recursing into the
+    /// light left over when a row gets blocked
+    fn cast_light<F, S>(&self, row: i32, mut start: f32, end: f32, opaque: &F, see: &mut S)
+    where
+        F: Fn(&SquareCoord) -> bool,
+        S: FnMut(SquareCoord),
+    {
+        if start < end {
+            return;
+        }
+        let mut next_start = start;
+        for j in row..=self.depth {
+            let mut blocked = false;
+            for dx in -j..=0 {
+                let dy = -j;
+                let (left, right) = slopes(dx, dy);
+                if start < right {
+                    continue;
+                } else if end > left {
+                    break;
+                }
+                let cell = self.cell(dx, dy);
+                if self.origin.distance(&cell) <= self.radius as u32 {
+                    see(cell);
+                }
+                if blocked {
+                    if opaque(&cell) {
+                        next_start = right;
+                    } else {
+                        blocked = false;
+                        start = next_start;
+                    }
+                } else if opaque(&cell) && j < self.depth {
+                    blocked = true;
+                    self.cast_light(j + 1, start, left, opaque, see);
+                    next_start = right;
+                }
+            }
+            if blocked {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::layers::GridLayer;
+
+    #[test]
+    fn walls_cast_shadows() {
+        let wall = SquareCoord { q: 1, r: 0 };
+        let behind = SquareCoord { q: 3, r: 0 };
+        let walls: GridLayer<SquareCoord, bool> = [(wall, true)].into_iter().collect();
+        let opaque = walls.matching(|w| *w);
+
+        let fov = SquareCoord::ZERO.field_of_view(4, &opaque);
+        assert!(fov.contains(&wall));
+        assert!(!fov.contains(&behind));
+        assert!(fov.contains(&SquareCoord { q: 0, r: 4 }));
+        assert!(!SquareCoord::ZERO.has_line_of_sight(&behind, &opaque));
+
+        let hex_wall = HexCoord { q: 1, r: 0 };
+        let fov = HexCoord::ZERO.field_of_view(3, |c| *c == hex_wall);
+        assert!(fov.contains(&hex_wall));
+        assert!(!fov.contains(&HexCoord { q: 3, r: 0 }));
+        assert!(fov.contains(&HexCoord { q: -3, r: 0 }));
+    }
+
+    #[test]
+    fn sight_agrees_with_the_field_of_view() {
+        let walls = [(2, 1), (-1, 2), (0, -3), (-2, -2), (3, -1), (1, 3)];
+        let opaque = |c: &SquareCoord| walls.contains(&(c.q, c.r));
+        let fov = SquareCoord::ZERO.field_of_view(5, opaque);
+        for cell in within(SquareCoord::ZERO, 5) {
+            assert_eq!(
+                SquareCoord::ZERO.has_line_of_sight(&cell, opaque),
+                fov.contains(&cell),
+                "{cell:?}"
+            );
+        }
+        // a scattered wood, around a viewer away from the origin
+        let trees = |c: &SquareCoord| (c.q * 7 + c.r * 13).rem_euclid(5) == 0;
+        let viewer = SquareCoord { q: 3, r: -1 };
+        let fov = viewer.field_of_view(8, trees);
+        for cell in within(viewer, 8) {
+            assert_eq!(
+                viewer.has_line_of_sight(&cell, trees),
+                fov.contains(&cell),
+                "{cell:?}"
+            );
+        }
+
+        let hex_walls = [HexCoord { q: 1, r: 0 }, HexCoord { q: -1, r: 2 }];
+        let opaque = |c: &HexCoord| hex_walls.contains(c);
+        let fov = HexCoord::ZERO.field_of_view(4, opaque);
+        for cell in within(HexCoord::ZERO, 4) {
+            assert_eq!(
+                HexCoord::ZERO.has_line_of_sight(&cell, opaque),
+                fov.contains(&cell)
+            );
+        }
+    }
+
+    #[test]
+    fn triangle_lines_are_connected() {
+        let from = TriangleCoord::new(0, 0, false);
+        let to = TriangleCoord::new(4, 2, true);
+        let line = from.line_to(&to);
+        assert_eq!(line.len() as u32, from.distance(&to) + 1);
+        for pair in line.windows(2) {
+            assert_eq!(pair[0].distance(&pair[1]), 1);
+        }
+    }
+}
@@ -0,0 +1,569 @@
+use bevy::utils::{HashMap, HashSet};
+use std::{error::Error, fmt, hash::Hash};
+
+use super::{
+    coordinates::{
+        Coords, HexCoord, HexDirection, PointyDirection, SquareCoord, SquareDirection,
+        TriangleCoord, TriangleDirection,
+    },
+    generation::mix,
+    layers::GridLayer,
+    terrain::{TerrainId, TerrainRegistry, UnknownTerrain},
+    GridLayout,
+};
+
+/// Coordinates with a neighbour across each of their edges, told apart by
+/// direction
+pub trait EdgeDirections: Coords + Copy + Eq + Hash {
+    type Direction: Copy + Eq + Hash + fmt::Debug + 'static;
+
+    /// Every direction an edge of some cell may face
+    fn directions() -> &'static [Self::Direction];
+
+    /// Directions the edges of this cell face
+    fn edge_directions(&self) -> Vec<Self::Direction> {
+        Self::directions().to_vec()
+    }
+
+    /// Cell across the edge facing `dir`
+    fn across(&self, dir: Self::Direction) -> Option<Self>;
+
+    /// Direction the same edge faces from the cell across it
+    fn opposite(dir: Self::Direction) -> Self::Direction;
+}
+
+impl EdgeDirections for TriangleCoord {
+    type Direction = TriangleDirection;
+
+    fn directions() -> &'static [TriangleDirection] {
+        &TriangleDirection::ALL
+    }
+
+    fn edge_directions(&self) -> Vec<TriangleDirection> {
+        self.directions().to_vec()
+    }
+
+    fn across(&self, dir: TriangleDirection) -> Option<TriangleCoord> {
+        self.neighbour(dir)
+    }
+
+    fn opposite(dir: TriangleDirection) -> TriangleDirection {
+        dir.opposite()
+    }
+}
+
+impl EdgeDirections for SquareCoord {
+    type Direction = SquareDirection;
+
+    fn directions() -> &'static [SquareDirection] {
+        &SquareDirection::ORTHOGONAL
+    }
+
+    fn across(&self, dir: SquareDirection) -> Option<SquareCoord> {
+        Some(self.neighbour(dir))
+    }
+
+    fn opposite(dir: SquareDirection) -> SquareDirection {
+        dir.opposite()
+    }
+}
+
+/// Hex edges are told apart by `PointyDirection`, flat hexes reading the
+/// `FlatDirection` of the same index
+impl EdgeDirections for HexCoord {
+    type Direction = PointyDirection;
+
+    fn directions() -> &'static [PointyDirection] {
+        &PointyDirection::ALL
+    }
+
+    fn across(&self, dir: PointyDirection) -> Option<HexCoord> {
+        Some(self.neighbour(dir))
+    }
+
+    fn opposite(dir: PointyDirection) -> PointyDirection {
+        dir.opposite()
+    }
+}
+
+/// Why no map came out of the rules
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WfcError {
+    /// A pinned cell names a tile the rules don't have
+    UnknownTile(String),
+    /// No arrangement of the tiles follows the rules around the pinned cells
+    Contradiction,
+    /// Backtracked `max_backtracks` times without finding an arrangement
+    GaveUp,
+    UnknownTerrain(UnknownTerrain),
+}
+
+impl fmt::Display for WfcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WfcError::UnknownTile(name) => write!(f, "unknown tile {:?}", name),
+            WfcError::Contradiction => write!(f, "no arrangement of the tiles follows the rules"),
+            WfcError::GaveUp => write!(f, "gave up backtracking"),
+            WfcError::UnknownTerrain(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for WfcError {}
+
+impl From<UnknownTerrain> for WfcError {
+    fn from(e: UnknownTerrain) -> Self {
+        WfcError::UnknownTerrain(e)
+    }
+}
+
+/// Tiles and which of them may border which, in which direction. Rules go
+/// both ways: A bordering B on its east means B borders A on its west.
+#[derive(Clone, Debug)]
+pub struct AdjacencyRules<K: EdgeDirections> {
+    tiles: Vec<String>,
+    weights: Vec<f32>,
+    allowed: HashSet<(usize, K::Direction, usize)>,
+}
+
+impl<K: EdgeDirections> Default for AdjacencyRules<K> {
+    fn default() -> Self {
+        AdjacencyRules {
+            tiles: Vec::new(),
+            weights: Vec::new(),
+            allowed: HashSet::new(),
+        }
+    }
+}
+
+impl<K: EdgeDirections> AdjacencyRules<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a tile, or sets the weight of one, picked in proportion to its
+    /// weight among the tiles a cell may still take. At most 64 tiles.
+    pub fn tile(&mut self, name: &str, weight: f32) -> &mut Self {
+        let index = self.index_or_add(name);
+        self.weights[index] = weight;
+        self
+    }
+
+    /// Lets `b` border `a` on the edge of `a` facing `dir`, adding either tile
+    /// with a weight of 1 if missing
+    pub fn allow(&mut self, a: &str, dir: K::Direction, b: &str) -> &mut Self {
+        let (a, b) = (self.index_or_add(a), self.index_or_add(b));
+        self.allowed.insert((a, dir, b));
+        self.allowed.insert((b, K::opposite(dir), a));
+        self
+    }
+
+    /// Lets `a` and `b` border each other in every direction
+    pub fn allow_all(&mut self, a: &str, b: &str) -> &mut Self {
+        for dir in K::directions() {
+            self.allow(a, *dir, b);
+        }
+        self
+    }
+
+    pub fn allows(&self, a: &str, dir: K::Direction, b: &str) -> bool {
+        match (self.index(a), self.index(b)) {
+            (Some(a), Some(b)) => self.allowed.contains(&(a, dir, b)),
+            _ => false,
+        }
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = &str> {
+        self.tiles.iter().map(String::as_str)
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.tiles.iter().position(|t| t == name)
+    }
+
+    fn index_or_add(&mut self, name: &str) -> usize {
+        self.index(name).unwrap_or_else(|| {
+            assert!(self.tiles.len() < 64, "at most 64 tiles");
+            self.tiles.push(name.to_string());
+            self.weights.push(1.0);
+            self.tiles.len() - 1
+        })
+    }
+
+    /// Tiles that may border each tile across each direction, as bit masks
+    fn masks(&self) -> Vec<Vec<u64>> {
+        let directions = K::directions();
+        let mut masks = vec![vec![0; directions.len()]; self.tiles.len()];
+        for (a, dir, b) in &self.allowed {
+            if let Some(d) = directions.iter().position(|d| d == dir) {
+                masks[*a][d] |= 1 << b;
+            }
+        }
+        masks
+    }
+}
+
+/// Stream of random numbers out of a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = mix(self.0);
+        self.0
+    }
+
+    /// Between 0 and 1
+    fn unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Tiles each cell may still take, narrowed down by picking tiles and
+/// spreading what they rule out
+struct Wave<'a> {
+    weights: &'a [f32],
+    masks: Vec<Vec<u64>>,
+    /// Direction and index of the cells across the edges of each cell
+    links: Vec<Vec<(usize, usize)>>,
+    options: Vec<u64>,
+}
+
+impl Wave<'_> {
+    /// Narrows the cells around the `changed` ones, false on a cell left
+    /// with no tile
+    fn propagate(&mut self, mut changed: Vec<usize>) -> bool {
+        while let Some(cell) = changed.pop() {
+            for &(dir, next) in &self.links[cell] {
+                let mut allowed = 0;
+                for tile in bits(self.options[cell]) {
+                    allowed |= self.masks[tile][dir];
+                }
+                let narrowed = self.options[next] & allowed;
+                if narrowed == self.options[next] {
+                    continue;
+                }
+                if narrowed == 0 {
+                    return false;
+                }
+                self.options[next] = narrowed;
+                changed.push(next);
+            }
+        }
+        true
+    }
+
+    /// Undecided cell whose tiles are the least uncertain, ties broken at
+    /// random
+    fn observe(&self, rng: &mut Rng) -> Option<usize> {
+        let mut best = None;
+        let mut lowest = f32::INFINITY;
+        for (cell, options) in self.options.iter().enumerate() {
+            if options.count_ones() < 2 {
+                continue;
+            }
+            let entropy = self.entropy(*options) + rng.unit() * 1e-3;
+            if entropy < lowest {
+                lowest = entropy;
+                best = Some(cell);
+            }
+        }
+        best
+    }
+
+    fn entropy(&self, options: u64) -> f32 {
+        let (mut total, mut sum) = (0.0, 0.0);
+        for tile in bits(options) {
+            let w = self.weights[tile].max(f32::EPSILON);
+            total += w;
+            sum += w * w.ln();
+        }
+        total.ln() - sum / total
+    }
+
+    /// One of the tiles the cell may take, by weight
+    fn pick(&self, cell: usize, rng: &mut Rng) -> usize {
+        let options = self.options[cell];
+        let total: f32 = bits(options).map(|t| self.weights[t].max(0.0)).sum();
+        let mut roll = rng.unit() * total;
+        let mut last = 0;
+        for tile in bits(options) {
+            last = tile;
+            roll -= self.weights[tile].max(0.0);
+            if roll < 0.0 {
+                break;
+            }
+        }
+        last
+    }
+}
+
+/// Indices of the bits set in a mask
+fn bits(mut mask: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        (mask != 0).then(|| {
+            let bit = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            bit
+        })
+    })
+}
+
+/// Wave Function Collapse: fills maps of any primitive with tiles bordering
+/// each other only as the rules allow. Cells are settled one at a time, the
+/// least uncertain first, undoing earlier picks on a dead end. The same seed,
+/// rules and pinned cells always give the same map.
+#[derive(Clone)]
+pub struct WaveFunctionCollapse<K: EdgeDirections> {
+    pub seed: u64,
+    pub rules: AdjacencyRules<K>,
+    /// Cells given their tile beforehand, by name
+    pub pinned: GridLayer<K, String>,
+    /// Picks undone before giving up
+    pub max_backtracks: usize,
+}
+
+impl<K: EdgeDirections> WaveFunctionCollapse<K> {
+    pub fn new(seed: u64, rules: AdjacencyRules<K>) -> Self {
+        WaveFunctionCollapse {
+            seed,
+            rules,
+            pinned: GridLayer::new(),
+            max_backtracks: 1000,
+        }
+    }
+
+    /// Tile of every cell of a `width` by `height` map, as terrain names
+    pub fn generate<T: GridLayout<Coord = K>>(
+        &self,
+        grid: &T,
+        width: u32,
+        height: u32,
+    ) -> Result<GridLayer<K, String>, WfcError> {
+        self.collapse(grid.cells(width, height))
+    }
+
+    /// Terrain layer of a `width` by `height` map, to stand as the terrain of
+    /// a grid
+    pub fn terrain<T: GridLayout<Coord = K>>(
+        &self,
+        grid: &T,
+        width: u32,
+        height: u32,
+        registry: &TerrainRegistry,
+    ) -> Result<GridLayer<K, TerrainId>, WfcError> {
+        Ok(registry.layer(&self.generate(grid, width, height)?)?)
+    }
+
+    /// Tile of each of the `cells`, only those among them bordering each
+    /// other. Pinned cells left out of them are ignored.
+    pub fn collapse(
+        &self,
+        cells: impl IntoIterator<Item = K>,
+    ) -> Result<GridLayer<K, String>, WfcError> {
+        let cells: Vec<K> = cells.into_iter().collect();
+        let index: HashMap<K, usize> = cells.iter().enumerate().map(|(i, c)| (*c, i)).collect();
+        let directions = K::directions();
+        let links = cells
+            .iter()
+            .map(|cell| {
+                cell.edge_directions()
+                    .into_iter()
+                    .filter_map(|dir| {
+                        let d = directions.iter().position(|d| *d == dir)?;
+                        Some((d, *index.get(&cell.across(dir)?)?))
+                    })
+                    .collect()
+            })
+            .collect();
+        let all = match self.rules.tiles.len() {
+            64 => u64::MAX,
+            n => (1 << n) - 1,
+        };
+        let mut wave = Wave {
+            weights: &self.rules.weights,
+            masks: self.rules.masks(),
+            links,
+            options: vec![all; cells.len()],
+        };
+        for (cell, name) in self.pinned.iter() {
+            let tile = self
+                .rules
+                .index(name)
+                .ok_or_else(|| WfcError::UnknownTile(name.clone()))?;
+            if let Some(i) = index.get(cell) {
+                wave.options[*i] = 1 << tile;
+            }
+        }
+        if wave.options.contains(&0) || !wave.propagate((0..cells.len()).collect()) {
+            return Err(WfcError::Contradiction);
+        }
+
+        let mut rng = Rng(mix(self.seed));
+        // options before each pick, with the cell and tile picked
+        let mut picks: Vec<(Vec<u64>, usize, usize)> = Vec::new();
+        let mut backtracks = 0;
+        while let Some(cell) = wave.observe(&mut rng) {
+            let tile = wave.pick(cell, &mut rng);
+            picks.push((wave.options.clone(), cell, tile));
+            wave.options[cell] = 1 << tile;
+            let mut settled = wave.propagate(vec![cell]);
+            while !settled {
+                let Some((options, cell, tile)) = picks.pop() else {
+                    return Err(WfcError::Contradiction);
+                };
+                if backtracks == self.max_backtracks {
+                    return Err(WfcError::GaveUp);
+                }
+                backtracks += 1;
+                wave.options = options;
+                wave.options[cell] &= !(1 << tile);
+                settled = wave.options[cell] != 0 && wave.propagate(vec![cell]);
+            }
+        }
+
+        Ok(cells
+            .into_iter()
+            .zip(wave.options)
+            .map(|(cell, options)| {
+                let tile = options.trailing_zeros() as usize;
+                (cell, self.rules.tiles[tile].clone())
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grids::{
+        coordinates::{HexOrientation, TriangleNeighbours},
+        primitives::{GridAlign, GridWrap, Hexes, Squares, Triangles},
+    };
+
+    /// Every two bordering cells of the map follow the rules
+    fn follows<K: EdgeDirections>(rules: &AdjacencyRules<K>, map: &GridLayer<K, String>) -> bool {
+        map.iter().all(|(cell, tile)| {
+            cell.edge_directions().into_iter().all(|dir| {
+                match cell.across(dir).and_then(|next| map.get(&next)) {
+                    Some(other) => rules.allows(tile, dir, other),
+                    None => true,
+                }
+            })
+        })
+    }
+
+    fn coast() -> AdjacencyRules<SquareCoord> {
+        let mut rules = AdjacencyRules::new();
+        rules
+            .tile("water", 3.0)
+            .allow_all("water", "water")
+            .allow_all("water", "sand")
+            .allow_all("sand", "sand")
+            .allow_all("sand", "grass")
+            .allow_all("grass", "grass")
+            // a road running north, only ever leaving grass southwards
+            .allow("road", SquareDirection::North, "road")
+            .allow("grass", SquareDirection::North, "road")
+            .allow("road", SquareDirection::East, "grass")
+            .allow("road", SquareDirection::West, "grass");
+        rules
+    }
+
+    #[test]
+    fn squares_follow_the_rules_around_pinned_cells() {
+        let squares = Squares {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        let mut wfc = WaveFunctionCollapse::new(7, coast());
+        let (shore, field) = (SquareCoord { q: 0, r: 0 }, SquareCoord { q: 6, r: 3 });
+        wfc.pinned.insert(shore, "water".to_string());
+        wfc.pinned.insert(field, "road".to_string());
+
+        let map = wfc.generate(&squares, 8, 8).unwrap();
+        assert_eq!(map.len(), 64);
+        assert!(follows(&wfc.rules, &map));
+        assert_eq!(map.get(&shore).unwrap(), "water");
+        assert_eq!(map.get(&field).unwrap(), "road");
+        // the road runs to the top edge
+        for r in 4..8 {
+            assert_eq!(map.get(&SquareCoord { q: 6, r }).unwrap(), "road");
+        }
+
+        let again = wfc.generate(&squares, 8, 8).unwrap();
+        assert!(map.iter().all(|(cell, tile)| again.get(cell) == Some(tile)));
+        let differs = (8..12).any(|seed| {
+            let other = WaveFunctionCollapse {
+                seed,
+                ..wfc.clone()
+            };
+            let other = other.generate(&squares, 8, 8).unwrap();
+            map.iter().any(|(cell, tile)| other.get(cell) != Some(tile))
+        });
+        assert!(differs);
+
+        wfc.pinned
+            .insert(SquareCoord { q: 1, r: 0 }, "grass".to_string());
+        assert_eq!(
+            wfc.generate(&squares, 8, 8).err(),
+            Some(WfcError::Contradiction)
+        );
+        wfc.pinned.insert(shore, "lava".to_string());
+        assert_eq!(
+            wfc.generate(&squares, 8, 8).err(),
+            Some(WfcError::UnknownTile("lava".to_string()))
+        );
+    }
+
+    #[test]
+    fn colourings_of_triangles_and_hexes() {
+        let triangles = Triangles {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            layer: 0.0,
+            neighbors: TriangleNeighbours::Strict,
+            wrap: GridWrap::default(),
+        };
+        let mut two = AdjacencyRules::<TriangleCoord>::new();
+        two.allow_all("light", "dark");
+        let map = WaveFunctionCollapse::new(1, two)
+            .generate(&triangles, 5, 5)
+            .unwrap();
+        assert_eq!(map.len(), 50);
+        // triangles pointing the same way never meet
+        let first = TriangleCoord::ZERO;
+        assert!(map.iter().all(|(c, tile)| {
+            (tile == map.get(&first).unwrap()) == (c.points_up() == first.points_up())
+        }));
+
+        let hexes = Hexes {
+            size: 1.0,
+            alignment: GridAlign::XZ,
+            orientation: HexOrientation::PointyUp,
+            layer: 0.0,
+            wrap: GridWrap::default(),
+        };
+        // hexes meet by threes, so two colours never do
+        let mut two = AdjacencyRules::<HexCoord>::new();
+        two.allow_all("light", "dark");
+        let wfc = WaveFunctionCollapse::new(1, two);
+        assert_eq!(
+            wfc.generate(&hexes, 4, 4).err(),
+            Some(WfcError::Contradiction)
+        );
+
+        let mut three = AdjacencyRules::<HexCoord>::new();
+        three
+            .allow_all("red", "green")
+            .allow_all("green", "blue")
+            .allow_all("blue", "red");
+        let mut wfc = WaveFunctionCollapse::new(3, three);
+        wfc.pinned.insert(HexCoord::ZERO, "blue".to_string());
+        let map = wfc.generate(&hexes, 6, 6).unwrap();
+        assert_eq!(map.len(), 36);
+        assert!(follows(&wfc.rules, &map));
+        assert_eq!(map.get(&HexCoord::ZERO).unwrap(), "blue");
+    }
+}
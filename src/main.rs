@@ -4,38 +4,29 @@ use smooth_bevy_cameras::{
     LookTransformPlugin,
 };
 
-mod grids;
-use grids::{coordinates::TriangleNeighbours, primitives::*, GridConfig, GridPlugin};
+use human_action::grids::{
+    asset::GridAssetPlugin, primitives::*, render::GridRenderPlugin, selection::SelectionPlugin,
+};
 
 fn main() {
     App::new()
         .insert_resource(Msaa::Sample4)
         .insert_resource(MouseWorldPos::default())
-        .add_plugins(DefaultPlugins)
+        .add_plugins(DefaultPlugins.set(AssetPlugin {
+            watch_for_changes: true,
+            ..default()
+        }))
         .add_plugin(LookTransformPlugin)
         .add_plugin(OrbitCameraPlugin::default())
-        .add_plugin(GridPlugin::<Triangles>::new(Triangles {
-            size: 0.1,
-            alignment: GridAlign::XY,
-            neighbors: TriangleNeighbours::Expanded,
-            layer: 1.0,
-        }))
-        // .add_plugin(GridPlugin::<Squares>::new(Squares {
-        //     size: 1.0,
-        //     alignment: GridAlign::XZ,
-        //     layer: 2.0,
-        // }))
+        .add_plugin(GridAssetPlugin::<Triangles>::new("grids/main.grid.ron"))
+        .add_plugin(GridRenderPlugin::<Triangles>::default())
+        .add_plugin(SelectionPlugin::<Triangles>::default())
         .add_startup_system(setup)
         .add_system(mouse_to_world_pos)
         .run();
 }
 
-fn setup(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    grid: Res<GridConfig<Triangles>>,
-) {
+fn setup(mut commands: Commands) {
     commands
         .spawn(Camera3dBundle::default())
         .insert(OrbitCameraBundle::new(
@@ -50,47 +41,6 @@ fn setup(
         transform: Transform::from_xyz(4.0, 8.0, 4.0),
         ..default()
     });
-    // commands.spawn(PbrBundle {
-    //     mesh: meshes.add(
-    //         shape::Plane {
-    //             size: 10.,
-    //             subdivisions: 4,
-    //         }
-    //         .into(),
-    //     ),
-    //     material: materials.add(StandardMaterial {
-    //         base_color: Color::GRAY,
-    //         ..default()
-    //     }),
-    //     transform: Transform::from_xyz(0.0, 0.0, 0.0),
-    //     ..default()
-    // });
-
-    let (mesh, coords) = grid.to_grid(10, 10);
-    let handle = meshes.add(mesh);
-    for c in coords {
-        let transform = c.to_world_pos(grid.0);
-        commands.spawn(PbrBundle {
-            mesh: handle.clone(),
-            material: materials.add(StandardMaterial {
-                base_color: Color::GREEN,
-                ..default()
-            }),
-            transform,
-            ..default()
-        });
-    }
-    // for coord in coordinates.iter() {
-    //     commands.spawn(PbrBundle {
-    //         mesh: meshes.add(mesh),
-    //         material: materials.add(StandardMaterial {
-    //             base_color: Color::GREEN,
-    //             ..default()
-    //         }),
-    //         transform: coord,
-    //         ..default()
-    //     });
-    // }
 }
 
 #[derive(Component)]
@@ -107,7 +57,6 @@ pub fn mouse_to_world_pos(
     let window = window.get_single().unwrap();
     if let Some(cursor) = window.cursor_position() {
         mouse_pos.0 = cursor_to_world(cursor, &camera_query, window);
-        dbg!(mouse_pos.0);
     }
 }
 
@@ -118,7 +67,7 @@ fn cursor_to_world(
 ) -> Vec2 {
     let (transform, camera) = camera_query.single();
 
-    let screen_size = Vec2::new(window.width() as f32, window.height() as f32);
+    let screen_size = Vec2::new(window.width(), window.height());
     let camera_position = transform.compute_matrix();
     let projection_matrix = camera.projection_matrix();
 